@@ -0,0 +1,78 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::model::Aggregate;
+
+/// User settings, loaded from `config.ron` in the ttt config directory and changeable at runtime
+/// with the `set` command.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Show when nodes were created and modified next to their text.
+    pub show_timestamps: bool,
+    /// Show how many of the leaves below a node are struck out.
+    pub show_progress: bool,
+    /// Show progress as a percentage rather than a count.
+    pub progress_percent: bool,
+    /// Strike out a node automatically when all of its children are struck out.
+    pub auto_strike_parents: bool,
+    /// Include progress in exported Markdown.
+    pub markdown_progress: bool,
+    /// How to combine the numbers below nodes that don't have a `rollup` property of their own.
+    pub rollup: Option<Aggregate>,
+    /// Location of a stored tree to look for templates in, as well as the current tree.
+    pub templates: Option<String>,
+    /// Keyboard macros by register, in the notation `parse_keys` reads, like `"dd}p<esc>"`.
+    pub macros: BTreeMap<char, String>,
+}
+
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .map(|d| d.join("ttt"))
+}
+
+impl Config {
+    /// Load the config file, or the default config if there isn't one.
+    pub fn load() -> Result<Config> {
+        let path = match config_dir() {
+            Some(d) => d.join("config.ron"),
+            None => return Ok(Config::default()),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(s) => ron::from_str(&s).with_context(|| format!("parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Change a setting by name.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let flag = match name {
+            "rollup" => {
+                self.rollup = match value {
+                    "off" => None,
+                    v => Some(v.parse()?),
+                };
+                return Ok(());
+            }
+            "templates" => {
+                self.templates = Some(value.to_owned());
+                return Ok(());
+            }
+            "show_timestamps" => &mut self.show_timestamps,
+            "show_progress" => &mut self.show_progress,
+            "progress_percent" => &mut self.progress_percent,
+            "auto_strike_parents" => &mut self.auto_strike_parents,
+            "markdown_progress" => &mut self.markdown_progress,
+            _ => anyhow::bail!("unknown setting: {name}"),
+        };
+        *flag = value
+            .parse()
+            .with_context(|| format!("invalid value for {name}: {value}"))?;
+        Ok(())
+    }
+}
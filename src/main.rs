@@ -21,8 +21,10 @@ use winit::event::{Event, WindowEvent};
 use winit::event_loop::EventLoopBuilder;
 use winit::window::{Window, WindowBuilder};
 
+mod config;
 mod model;
 mod presenter;
+mod recurrence;
 mod storage;
 mod view;
 
@@ -191,7 +193,33 @@ impl Renderer {
     }
 }
 
+/// Add a node to the inbox of a stored tree without opening the editor, for
+/// `ttt capture <text> --to <tree>`.
+fn capture(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut text = Vec::new();
+    let mut to = None;
+    while let Some(arg) = args.next() {
+        if arg == "--to" {
+            to = Some(args.next().context("missing tree after --to")?);
+        } else {
+            text.push(arg);
+        }
+    }
+    let text = text.join(" ");
+    if text.is_empty() {
+        anyhow::bail!("usage: ttt capture <text> --to <tree>");
+    }
+    let (_, mut storage) =
+        storage::open_storage(&to.context("missing --to <tree>")?).context("open tree")?;
+    storage.capture(&text)
+}
+
 fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("capture") {
+        return capture(args);
+    }
+
     let event_loop = EventLoopBuilder::new()
         .build()
         .context("build event loop")?;
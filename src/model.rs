@@ -1,22 +1,116 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::{self, Write as IoWrite};
+use uuid::Uuid;
 
 pub type NodeId = usize;
 
 pub const ROOT_PARENT_ID: NodeId = 0;
 
+/// Property marking the node that captured text is added under.
+pub const INBOX_PROPERTY: &str = "inbox";
+
+/// A span of time spent on a node. Entries without an end are still running.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClockEntry {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl ClockEntry {
+    pub fn duration(&self) -> Duration {
+        self.end.unwrap_or_else(Utc::now) - self.start
+    }
+
+    /// Split the entry at local midnights, giving the time spent on each day.
+    pub fn per_day(&self) -> Vec<(NaiveDate, Duration)> {
+        let end = self.end.unwrap_or_else(Utc::now).with_timezone(&Local);
+        let mut cur = self.start.with_timezone(&Local);
+        let mut days = Vec::new();
+        while cur.date_naive() < end.date_naive() {
+            let next_day = cur
+                .date_naive()
+                .succ_opt()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .and_then(|d| d.and_local_timezone(Local).earliest())
+                .unwrap_or(end);
+            days.push((cur.date_naive(), next_day - cur));
+            cur = next_day;
+        }
+        days.push((end.date_naive(), end - cur));
+        days
+    }
+}
+
+/// Format a duration as hours and minutes, like `1h 05m`.
+pub fn format_duration(d: Duration) -> String {
+    let minutes = d.num_minutes();
+    if minutes < 60 {
+        format!("{minutes}m")
+    } else {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Node {
     pub id: NodeId,
+    /// Globally unique identifier that stays the same across saves and moves, unlike `id` which is
+    /// only unique within a single tree.
+    #[serde(default)]
+    pub uid: Uuid,
     pub text: String,
     pub parent: NodeId,
     pub children: Vec<NodeId>,
     #[serde(default)]
     pub struckout: bool,
+    /// Whether the node was struck out by `auto_strike_parents` rather than by hand, so that it
+    /// can be un-struck again when one of its children is.
+    #[serde(default)]
+    pub auto_struck: bool,
+    /// If set, this node is a mirror of another node, and shares its text, state and children.
+    #[serde(default)]
+    pub mirror_of: Option<NodeId>,
+    /// When the node was created. Nodes from trees saved before this was recorded have no value.
+    #[serde(default)]
+    pub created: Option<DateTime<Utc>>,
+    /// When the node's text or state was last changed, or it was last moved.
+    #[serde(default)]
+    pub modified: Option<DateTime<Utc>>,
+    /// Arbitrary key/value metadata attached to the node.
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    /// Time spent on the node.
+    #[serde(default)]
+    pub clock: Vec<ClockEntry>,
+    /// When a recurring node was completed, oldest first.
+    #[serde(default)]
+    pub completions: Vec<DateTime<Utc>>,
+    /// Unique IDs of the nodes that have to be struck out before this one can be worked on.
+    #[serde(default)]
+    pub blocked_by: Vec<Uuid>,
 }
 
 impl Node {
+    /// The number this node carries, from its `value` property or a number at the end of its text.
+    pub fn value(&self) -> Option<f64> {
+        let s = match self.properties.get("value") {
+            Some(v) => v.as_str(),
+            None => self.text.split_whitespace().last()?,
+        };
+        // allow units like currency symbols in front of the number
+        s.trim_start_matches(|c: char| !(c.is_ascii_digit() || c == '-' || c == '.'))
+            .parse()
+            .ok()
+    }
+
+    /// Record that the node was modified just now.
+    pub fn touch(&mut self) {
+        self.modified = Some(Utc::now());
+    }
+
     pub fn parent(&self) -> Option<NodeId> {
         if self.parent == ROOT_PARENT_ID {
             None
@@ -26,11 +120,167 @@ impl Node {
     }
 }
 
+/// Restricts which nodes are shown based on when they were created or modified.
+#[derive(Clone, Copy, Debug)]
+pub enum TimeFilter {
+    CreatedWithin(Duration),
+    ModifiedWithin(Duration),
+}
+
+impl TimeFilter {
+    pub fn matches(&self, node: &Node) -> bool {
+        let (time, within) = match self {
+            TimeFilter::CreatedWithin(d) => (node.created, d),
+            TimeFilter::ModifiedWithin(d) => (node.modified, d),
+        };
+        time.is_some_and(|t| Utc::now() - t <= *within)
+    }
+}
+
+/// Compare strings so that runs of digits are ordered by their numeric value, so that "item 2"
+/// comes before "item 10".
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |it: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(d) = it.next_if(char::is_ascii_digit) {
+                        digits.push(d);
+                    }
+                    digits.trim_start_matches('0').to_owned()
+                };
+                let (x, y) = (take_number(&mut a), take_number(&mut b));
+                match x.len().cmp(&y.len()).then_with(|| x.cmp(&y)) {
+                    Ordering::Equal => {}
+                    o => return o,
+                }
+            }
+            (Some(x), Some(y)) => match x.cmp(&y) {
+                Ordering::Equal => {
+                    a.next();
+                    b.next();
+                }
+                o => return o,
+            },
+        }
+    }
+}
+
+/// What to compare nodes by when sorting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Text,
+    Natural,
+    Struckout,
+    Created,
+    Modified,
+    Property(String),
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(SortKey::Text),
+            "num" => Ok(SortKey::Natural),
+            "done" => Ok(SortKey::Struckout),
+            "created" => Ok(SortKey::Created),
+            "modified" => Ok(SortKey::Modified),
+            _ => match s.strip_prefix("prop:") {
+                Some(p) => Ok(SortKey::Property(p.to_owned())),
+                None => Err(anyhow::anyhow!("unknown sort key: {s}")),
+            },
+        }
+    }
+}
+
+impl SortKey {
+    pub fn compare(&self, a: &Node, b: &Node) -> Ordering {
+        match self {
+            SortKey::Text => a.text.cmp(&b.text),
+            SortKey::Natural => natural_cmp(&a.text, &b.text),
+            SortKey::Struckout => a.struckout.cmp(&b.struckout),
+            SortKey::Created => a.created.cmp(&b.created),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            // nodes without the property go last
+            SortKey::Property(p) => match (a.properties.get(p), b.properties.get(p)) {
+                (Some(x), Some(y)) => natural_cmp(x, y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        }
+    }
+}
+
+/// How the values below a node are combined into one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl std::str::FromStr for Aggregate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sum" => Ok(Aggregate::Sum),
+            "min" => Ok(Aggregate::Min),
+            "max" => Ok(Aggregate::Max),
+            "avg" => Ok(Aggregate::Avg),
+            _ => Err(anyhow::anyhow!("unknown aggregate: {s}")),
+        }
+    }
+}
+
+impl Aggregate {
+    pub fn apply(&self, values: &[f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+        Some(match self {
+            Aggregate::Sum => values.iter().sum(),
+            Aggregate::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregate::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregate::Avg => values.iter().sum::<f64>() / values.len() as f64,
+        })
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Aggregate::Sum => "Σ",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Avg => "avg",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Tree {
     next_id: NodeId,
     root_id: NodeId,
     pub nodes: HashMap<NodeId, Node>,
+    #[serde(default)]
+    marks: BTreeMap<char, NodeId>,
+    /// Which node has each unique ID, kept up to date as nodes are added and rebuilt by
+    /// `index_uids` after loading.
+    #[serde(skip)]
+    uid_index: HashMap<Uuid, NodeId>,
+    /// Counts changes to the nodes, so that anything worked out from them knows when it is out of
+    /// date.
+    #[serde(skip)]
+    generation: u64,
 }
 
 impl Default for Tree {
@@ -48,6 +298,9 @@ impl Tree {
             nodes: HashMap::new(),
             next_id: 1,
             root_id: 0,
+            marks: BTreeMap::new(),
+            uid_index: HashMap::new(),
+            generation: 0,
         }
     }
 
@@ -61,16 +314,29 @@ impl Tree {
     }
 
     fn create_node(&mut self, text: String, parent: NodeId) -> NodeId {
+        self.generation += 1;
         let id = self.next_id;
         self.next_id += 1;
+        let now = Utc::now();
+        let uid = Uuid::new_v4();
+        self.uid_index.insert(uid, id);
         self.nodes.insert(
             id,
             Node {
                 id,
+                uid,
                 text,
                 parent,
                 children: Vec::new(),
                 struckout: false,
+                auto_struck: false,
+                mirror_of: None,
+                created: Some(now),
+                modified: Some(now),
+                properties: BTreeMap::new(),
+                clock: Vec::new(),
+                completions: Vec::new(),
+                blocked_by: Vec::new(),
             },
         );
         id
@@ -116,14 +382,319 @@ impl Tree {
         id
     }
 
+    /// Give a unique ID to every node that is missing one, for trees saved before nodes had them.
+    /// Returns true if any node was changed.
+    pub fn assign_missing_uids(&mut self) -> bool {
+        let mut changed = false;
+        for node in self.nodes.values_mut() {
+            if node.uid.is_nil() {
+                node.uid = Uuid::new_v4();
+                self.uid_index.insert(node.uid, node.id);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Rebuild the index `find_by_uid` uses, for a tree that was just loaded.
+    pub fn index_uids(&mut self) {
+        self.uid_index = self.nodes.values().map(|n| (n.uid, n.id)).collect();
+    }
+
+    pub fn find_by_uid(&self, uid: Uuid) -> Option<NodeId> {
+        self.uid_index
+            .get(&uid)
+            .copied()
+            .filter(|n| self.nodes.contains_key(n))
+    }
+
     pub fn node(&self, id: NodeId) -> &Node {
         &self.nodes[&id]
     }
 
     pub fn node_mut(&mut self, id: usize) -> &mut Node {
+        self.generation += 1;
         self.nodes.get_mut(&id).unwrap()
     }
 
+    /// A number that changes whenever a node is added or changed.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Iterate over `id` and then each of its ancestors up to the root of the tree.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(Some(id), |n| self.node(*n).parent())
+    }
+
+    /// Check if a node is part of the tree, rather than cut out of it or removed for good.
+    pub fn is_attached(&self, id: NodeId) -> bool {
+        self.nodes.contains_key(&id) && self.ancestors(id).last() == Some(self.root_id)
+    }
+
+    /// Remember `id` under the mark `name`.
+    pub fn set_mark(&mut self, name: char, id: NodeId) {
+        self.marks.insert(name, id);
+    }
+
+    /// The node marked `name`, if it is still part of the tree.
+    pub fn mark(&self, name: char) -> Option<NodeId> {
+        self.marks
+            .get(&name)
+            .copied()
+            .filter(|n| self.nodes.contains_key(n) && self.is_attached(*n))
+    }
+
+    /// The first line of the text of each node from the root down to `id`, joined with `>`.
+    pub fn path_text(&self, id: NodeId) -> String {
+        let mut path: Vec<&str> = self
+            .ancestors(id)
+            .map(|n| self.content(n).text.lines().next().unwrap_or(""))
+            .filter(|t| !t.is_empty())
+            .collect();
+        path.reverse();
+        path.join(" > ")
+    }
+
+    /// Find a node in the tree that has the property `key` set.
+    pub fn find_with_property(&self, key: &str) -> Option<NodeId> {
+        self.nodes
+            .values()
+            .filter(|n| n.properties.contains_key(key) && self.is_attached(n.id))
+            .map(|n| n.id)
+            .min()
+    }
+
+    /// Find a node in the tree that has the property `key` set to `value`.
+    pub fn find_with_property_value(&self, key: &str, value: &str) -> Option<NodeId> {
+        self.nodes
+            .values()
+            .filter(|n| n.properties.get(key).is_some_and(|v| v == value) && self.is_attached(n.id))
+            .map(|n| n.id)
+            .min()
+    }
+
+    /// Add a node with `text` to the end of the inbox, the node with the `inbox` property, which is
+    /// created at the end of the tree if there isn't one yet.
+    pub fn add_to_inbox(&mut self, text: String, created: DateTime<Utc>) -> NodeId {
+        let inbox = match self.find_with_property(INBOX_PROPERTY) {
+            Some(n) => self.source(n),
+            None => {
+                let n = self.add_node("Inbox".into(), self.root_id);
+                self.node_mut(n)
+                    .properties
+                    .insert(INBOX_PROPERTY.into(), "true".into());
+                n
+            }
+        };
+        let id = self.add_node(text, inbox);
+        let node = self.node_mut(id);
+        node.created = Some(created);
+        node.modified = Some(created);
+        id
+    }
+
+    /// Resolve a node to the node that actually holds its content, following mirrors.
+    pub fn source(&self, mut id: NodeId) -> NodeId {
+        while let Some(src) = self.node(id).mirror_of {
+            id = src;
+        }
+        id
+    }
+
+    /// The node holding the text, state and children for `id`. For ordinary nodes this is the
+    /// node itself, for mirrors it is the mirrored node.
+    pub fn content(&self, id: NodeId) -> &Node {
+        self.node(self.source(id))
+    }
+
+    pub fn content_mut(&mut self, id: NodeId) -> &mut Node {
+        self.node_mut(self.source(id))
+    }
+
+    pub fn is_mirror(&self, id: NodeId) -> bool {
+        self.node(id).mirror_of.is_some()
+    }
+
+    /// Find every node whose content or any of its descendants satisfies `pred`: the nodes that
+    /// do, their ancestors, and the mirrors of any of those along with their ancestors.
+    pub fn subtrees_matching(&self, pred: impl Fn(&Node) -> bool) -> HashSet<NodeId> {
+        let mut mirrors: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        for n in self.nodes.values() {
+            if let Some(src) = n.mirror_of {
+                mirrors.entry(self.source(src)).or_default().push(n.id);
+            }
+        }
+        let mut found = HashSet::new();
+        let mut stack: Vec<NodeId> = self
+            .nodes
+            .values()
+            .filter(|n| n.mirror_of.is_none() && pred(n))
+            .map(|n| n.id)
+            .collect();
+        while let Some(n) = stack.pop() {
+            if !found.insert(n) {
+                continue;
+            }
+            stack.extend(self.node(n).parent());
+            stack.extend(mirrors.get(&n).into_iter().flatten());
+        }
+        found
+    }
+
+    /// Create a new node that mirrors `source`, placed in `parent` after `after` or at the end.
+    pub fn add_mirror(&mut self, source: NodeId, parent: NodeId, after: Option<NodeId>) -> NodeId {
+        let source = self.source(source);
+        let id = if let Some(after) = after {
+            self.insert_node(String::new(), parent, after, true)
+        } else {
+            self.add_node(String::new(), parent)
+        };
+        self.node_mut(id).mirror_of = Some(source);
+        id
+    }
+
+    /// Check if placing `node` under `new_parent` would make the tree contain itself through a
+    /// mirror, which would make it infinitely deep.
+    pub fn would_create_cycle(&self, node: NodeId, new_parent: NodeId) -> bool {
+        let mut ancestors = Vec::new();
+        let mut cur = Some(self.source(new_parent));
+        while let Some(n) = cur {
+            ancestors.push(n);
+            cur = self.node(n).parent();
+        }
+
+        let mut stack = vec![node];
+        while let Some(n) = stack.pop() {
+            let src = self.source(n);
+            if ancestors.contains(&src) {
+                return true;
+            }
+            stack.extend(self.node(src).children.iter().copied());
+        }
+        false
+    }
+
+    /// The nodes that `id` is waiting on that are still in the tree.
+    pub fn blockers(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.content(id)
+            .blocked_by
+            .iter()
+            .filter_map(|uid| self.find_by_uid(*uid))
+            .filter(|n| self.is_attached(*n))
+    }
+
+    /// Check if `id` is waiting on any node that isn't struck out yet.
+    pub fn is_blocked(&self, id: NodeId) -> bool {
+        self.blockers(id).any(|b| !self.content(b).struckout)
+    }
+
+    /// Check if making `node` wait on `blocker` would make them end up waiting on each other.
+    pub fn would_create_dependency_cycle(&self, node: NodeId, blocker: NodeId) -> bool {
+        let node = self.source(node);
+        let mut seen = HashSet::new();
+        let mut stack = vec![self.source(blocker)];
+        while let Some(n) = stack.pop() {
+            if n == node {
+                return true;
+            }
+            if seen.insert(n) {
+                stack.extend(self.blockers(n));
+            }
+        }
+        false
+    }
+
+    /// Check if `redirect_dependencies` would make nodes end up waiting on each other.
+    pub fn redirect_would_create_dependency_cycle(&self, from: NodeId, to: NodeId) -> bool {
+        let (from_uid, to_uid) = (self.node(from).uid, self.node(to).uid);
+        let blockers_ok = self.node(from).blocked_by.iter().all(|uid| {
+            *uid == to_uid
+                || self
+                    .find_by_uid(*uid)
+                    .is_none_or(|b| !self.would_create_dependency_cycle(to, b))
+        });
+        let dependents_ok = self
+            .nodes
+            .values()
+            .filter(|n| n.id != to && n.blocked_by.contains(&from_uid))
+            .all(|n| !self.would_create_dependency_cycle(n.id, to));
+        !(blockers_ok && dependents_ok)
+    }
+
+    /// Make `to` wait on what `from` waits on, and everything waiting on `from` wait on `to`
+    /// instead, for when `from` is merged into `to`. Check
+    /// `redirect_would_create_dependency_cycle` first.
+    pub fn redirect_dependencies(&mut self, from: NodeId, to: NodeId) {
+        let (from_uid, to_uid) = (self.node(from).uid, self.node(to).uid);
+        for uid in self.node(from).blocked_by.clone() {
+            if uid != to_uid && !self.node(to).blocked_by.contains(&uid) {
+                self.node_mut(to).blocked_by.push(uid);
+            }
+        }
+        self.generation += 1;
+        for n in self.nodes.values_mut() {
+            if n.blocked_by.contains(&from_uid) {
+                n.blocked_by.retain(|u| *u != from_uid);
+                if n.id != to && !n.blocked_by.contains(&to_uid) {
+                    n.blocked_by.push(to_uid);
+                }
+            }
+        }
+    }
+
+    /// Find every leaf below `root` that can be worked on now, in order: ones that aren't struck
+    /// out or waiting on another node, and don't have an ancestor that is. Mirrors are skipped so
+    /// that each node is only listed once.
+    pub fn actionable_leaves(&self, root: NodeId) -> Vec<NodeId> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![root];
+        while let Some(n) = stack.pop() {
+            let node = self.node(n);
+            if node.mirror_of.is_some() || node.struckout || self.is_blocked(n) {
+                continue;
+            }
+            if node.children.is_empty() {
+                if n != root {
+                    leaves.push(n);
+                }
+            } else {
+                stack.extend(node.children.iter().rev());
+            }
+        }
+        leaves
+    }
+
+    /// Take `node` and everything below it out of the tree for good, for when they have moved to
+    /// another tree or been joined into another node. Check `mirrored_outside` first, since
+    /// mirrors of the removed nodes would be left pointing at nothing.
+    pub fn remove_subtree(&mut self, node: NodeId) {
+        self.cut_node(node);
+        self.generation += 1;
+        let mut stack = vec![node];
+        while let Some(n) = stack.pop() {
+            if let Some(removed) = self.nodes.remove(&n) {
+                if self.uid_index.get(&removed.uid) == Some(&n) {
+                    self.uid_index.remove(&removed.uid);
+                }
+                stack.extend(removed.children);
+            }
+        }
+    }
+
+    /// Check if any node outside the subtree under `node` mirrors a node inside it.
+    pub fn mirrored_outside(&self, node: NodeId) -> bool {
+        let mut inside = HashSet::new();
+        let mut stack = vec![node];
+        while let Some(n) = stack.pop() {
+            inside.insert(n);
+            stack.extend(self.node(n).children.iter().copied());
+        }
+        self.nodes
+            .values()
+            .any(|n| !inside.contains(&n.id) && n.mirror_of.is_some_and(|s| inside.contains(&s)))
+    }
+
     pub fn cut_node(&mut self, node: NodeId) {
         if let Some(parent) = self.node(node).parent() {
             let parent = self.node_mut(parent);
@@ -167,17 +738,25 @@ impl Tree {
         }
     }
 
+    /// Deep copy `node` into `new_parent`. Mirrors are copied as new mirrors of the same source
+    /// rather than copying the mirrored subtree.
     pub fn clone_node(
         &mut self,
         node: NodeId,
         new_parent: NodeId,
         after: Option<NodeId>,
     ) -> NodeId {
+        if let Some(source) = self.node(node).mirror_of {
+            return self.add_mirror(source, new_parent, after);
+        }
+
         let new_node = if let Some(after) = after {
             self.insert_node(self.node(node).text.clone(), new_parent, after, true)
         } else {
             self.add_node(self.node(node).text.clone(), new_parent)
         };
+        self.node_mut(new_node).properties = self.node(node).properties.clone();
+        self.node_mut(new_node).blocked_by = self.node(node).blocked_by.clone();
 
         for child in self.node(node).children.clone() {
             self.clone_node(child, new_node, None);
@@ -186,6 +765,36 @@ impl Tree {
         new_node
     }
 
+    /// Copy a subtree from another tree into this one as the last child of `parent`. If
+    /// `keep_uids` is set the copies keep their unique IDs, for when they replace the originals.
+    /// Mirrors are copied as their content, since the source may not exist in this tree.
+    pub fn import_subtree(
+        &mut self,
+        from: &Tree,
+        node: NodeId,
+        parent: NodeId,
+        keep_uids: bool,
+    ) -> NodeId {
+        let src = from.content(node);
+        let id = self.add_node(src.text.clone(), parent);
+        if keep_uids {
+            let uid = from.node(node).uid;
+            let old_uid = std::mem::replace(&mut self.node_mut(id).uid, uid);
+            self.uid_index.remove(&old_uid);
+            self.uid_index.insert(uid, id);
+        }
+        let new_node = self.node_mut(id);
+        new_node.struckout = src.struckout;
+        new_node.created = src.created;
+        new_node.modified = src.modified;
+        new_node.properties = src.properties.clone();
+        new_node.blocked_by = src.blocked_by.clone();
+        for child in src.children.iter() {
+            self.import_subtree(from, *child, id, keep_uids);
+        }
+        id
+    }
+
     /// Move the currently selected node to the next child node. If moving to the next child would
     /// move us past the end of the tree, then None is returned.
     /// This has the effect of moving "down" the tree.
@@ -246,8 +855,259 @@ impl Tree {
         }
     }
 
-    pub fn write_markdown<W: IoWrite>(&self, writer: &mut W) -> io::Result<()> {
-        self.append_markdown(writer, self.root_id, 0)
+    /// Reorder the children of `node` by `key`, optionally doing the same for every descendant.
+    pub fn sort_children(
+        &mut self,
+        node: NodeId,
+        key: &SortKey,
+        descending: bool,
+        recursive: bool,
+    ) {
+        let node = self.source(node);
+        let out_of_order = |t: &Tree, a: NodeId, b: NodeId| {
+            let o = key.compare(t.content(a), t.content(b));
+            let o = if descending { o.reverse() } else { o };
+            o == Ordering::Greater
+        };
+        // an insertion sort made of `swap_node` steps, the same reordering as moving nodes by hand
+        for i in 1..self.node(node).children.len() {
+            for j in (1..=i).rev() {
+                let children = &self.node(node).children;
+                let (prev, cur) = (children[j - 1], children[j]);
+                if !out_of_order(self, prev, cur) {
+                    break;
+                }
+                self.swap_node(cur, -1);
+            }
+        }
+        if recursive {
+            for c in self.node(node).children.clone() {
+                self.sort_children(c, key, descending, recursive);
+            }
+        }
+    }
+
+    /// Sort `nodes` among themselves, by reordering them within the places they already have in
+    /// their parents. Nodes with different parents are only sorted with their siblings.
+    pub fn sort_nodes(&mut self, nodes: &[NodeId], key: &SortKey, descending: bool) {
+        let mut parents: Vec<NodeId> = nodes
+            .iter()
+            .filter_map(|n| self.node(*n).parent())
+            .collect();
+        parents.sort_unstable();
+        parents.dedup();
+        for p in parents {
+            let mut children = std::mem::take(&mut self.node_mut(p).children);
+            let slots: Vec<usize> = (0..children.len())
+                .filter(|i| nodes.contains(&children[*i]))
+                .collect();
+            let mut sorted: Vec<NodeId> = slots.iter().map(|i| children[*i]).collect();
+            sorted.sort_by(|a, b| {
+                let o = key.compare(self.content(*a), self.content(*b));
+                if descending {
+                    o.reverse()
+                } else {
+                    o
+                }
+            });
+            for (i, n) in slots.into_iter().zip(sorted) {
+                children[i] = n;
+            }
+            self.node_mut(p).children = children;
+        }
+    }
+
+    /// Put `nodes` in the order they appear in the tree, leaving out any that are below another
+    /// one of them or not in the tree at all.
+    pub fn top_level_in_order(&self, nodes: &[NodeId]) -> Vec<NodeId> {
+        let mut ordered = Vec::new();
+        let mut stack = vec![self.root_id];
+        while let Some(n) = stack.pop() {
+            if nodes.contains(&n) {
+                ordered.push(n);
+            } else if self.node(n).mirror_of.is_none() {
+                stack.extend(self.node(n).children.iter().rev());
+            }
+        }
+        ordered
+    }
+
+    /// Count the leaves below `id` that are done, and the total number of leaves. Leaves count as
+    /// done if they or any node above them (up to `id`) are struck out.
+    pub fn progress(&self, id: NodeId) -> (usize, usize) {
+        fn count(tree: &Tree, id: NodeId, struck_above: bool) -> (usize, usize) {
+            let node = tree.content(id);
+            let done = struck_above || node.struckout;
+            if node.children.is_empty() {
+                return (done as usize, 1);
+            }
+            node.children
+                .iter()
+                .map(|c| count(tree, *c, done))
+                .fold((0, 0), |(d, t), (cd, ct)| (d + cd, t + ct))
+        }
+
+        let node = self.content(id);
+        node.children
+            .iter()
+            .map(|c| count(self, *c, node.struckout))
+            .fold((0, 0), |(d, t), (cd, ct)| (d + cd, t + ct))
+    }
+
+    /// The values of the leaves below `id` that have one.
+    pub fn leaf_values(&self, id: NodeId) -> Vec<f64> {
+        let node = self.content(id);
+        if node.children.is_empty() {
+            node.value().into_iter().collect()
+        } else {
+            node.children
+                .iter()
+                .flat_map(|c| self.leaf_values(*c))
+                .collect()
+        }
+    }
+
+    /// Find how values should be rolled up at `id`, from the `rollup` property on it or its closest
+    /// ancestor. Returns None if no node sets it, and Some(None) if it is set to `off`.
+    pub fn rollup_aggregate(&self, id: NodeId) -> Option<Option<Aggregate>> {
+        self.ancestors(id)
+            .find_map(|n| self.content(n).properties.get("rollup"))
+            .map(|r| r.parse().ok())
+    }
+
+    /// Find the names of the `{{variables}}` used in the text of `id` and its descendants, in the
+    /// order they first appear.
+    pub fn template_vars(&self, id: NodeId) -> Vec<String> {
+        let mut vars = Vec::new();
+        let mut stack = vec![id];
+        while let Some(n) = stack.pop() {
+            let node = self.content(n);
+            let mut rest = node.text.as_str();
+            while let Some((name, after)) =
+                rest.split_once("{{").and_then(|(_, r)| r.split_once("}}"))
+            {
+                if !vars.iter().any(|v| v == name) {
+                    vars.push(name.to_owned());
+                }
+                rest = after;
+            }
+            stack.extend(node.children.iter().rev());
+        }
+        vars
+    }
+
+    /// Replace each `{{name}}` that has a value in `values` in the text of `id` and its
+    /// descendants. The text is scanned once, so `{{...}}` in a value is left as it is.
+    pub fn substitute_vars(&mut self, id: NodeId, values: &[(String, String)]) {
+        let text = &self.content(id).text;
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some((before, name, after)) = rest
+            .split_once("{{")
+            .and_then(|(b, r)| r.split_once("}}").map(|(n, a)| (b, n, a)))
+        {
+            out.push_str(before);
+            match values.iter().find(|(n, _)| n == name) {
+                Some((_, value)) => out.push_str(value),
+                None => {
+                    out.push_str("{{");
+                    out.push_str(name);
+                    out.push_str("}}");
+                }
+            }
+            rest = after;
+        }
+        out.push_str(rest);
+        if out != *text {
+            self.content_mut(id).text = out;
+        }
+        for child in self.content(id).children.clone() {
+            self.substitute_vars(child, values);
+        }
+    }
+
+    /// Find the node with a running clock, if there is one.
+    pub fn running_clock(&self) -> Option<NodeId> {
+        self.nodes
+            .values()
+            .find(|n| n.clock.last().is_some_and(|e| e.end.is_none()))
+            .map(|n| n.id)
+    }
+
+    /// Total time clocked on `id` and all of its descendants.
+    pub fn clocked_time(&self, id: NodeId) -> Duration {
+        self.clock_rollup(id).0
+    }
+
+    /// Total time clocked on `id` and all of its descendants, along with how many of their
+    /// clocks are still running, which is how fast the total is growing.
+    pub fn clock_rollup(&self, id: NodeId) -> (Duration, usize) {
+        let node = self.content(id);
+        let own = node
+            .clock
+            .iter()
+            .fold((Duration::zero(), 0), |(total, running), e| {
+                (total + e.duration(), running + e.end.is_none() as usize)
+            });
+        // mirrors below are left out so that the time isn't counted a second time
+        node.children
+            .iter()
+            .filter(|c| !self.is_mirror(**c))
+            .map(|c| self.clock_rollup(*c))
+            .fold(own, |(total, running), (t, r)| (total + t, running + r))
+    }
+
+    /// Write a Markdown report of the time clocked on `root` and its descendants, with the time
+    /// spent on each node for each day followed by the total for each node.
+    pub fn write_clock_report<W: IoWrite>(&self, writer: &mut W, root: NodeId) -> io::Result<()> {
+        let mut per_day: BTreeMap<NaiveDate, BTreeMap<String, Duration>> = BTreeMap::new();
+        let mut totals: BTreeMap<String, Duration> = BTreeMap::new();
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            // mirrored time is reported under the node it really belongs to
+            if id != root && self.is_mirror(id) {
+                continue;
+            }
+            let node = self.content(id);
+            if !node.clock.is_empty() {
+                let path = self.path_text(id);
+                for entry in node.clock.iter() {
+                    for (day, d) in entry.per_day() {
+                        *per_day
+                            .entry(day)
+                            .or_default()
+                            .entry(path.clone())
+                            .or_insert_with(Duration::zero) += d;
+                    }
+                    *totals.entry(path.clone()).or_insert_with(Duration::zero) += entry.duration();
+                }
+            }
+            stack.extend(node.children.iter());
+        }
+
+        for (day, nodes) in per_day {
+            writeln!(writer, "## {day}")?;
+            for (path, d) in nodes {
+                writeln!(writer, "- {path}: {}", format_duration(d))?;
+            }
+            writeln!(writer)?;
+        }
+        writeln!(writer, "## Total")?;
+        for (path, d) in totals {
+            writeln!(writer, "- {path}: {}", format_duration(d))?;
+        }
+        Ok(())
+    }
+
+    /// Write `root` and its descendants as a nested Markdown list. If `with_progress` is set,
+    /// nodes with children are followed by their progress, like `[3/7]`.
+    pub fn write_markdown<W: IoWrite>(
+        &self,
+        writer: &mut W,
+        root: NodeId,
+        with_progress: bool,
+    ) -> io::Result<()> {
+        self.append_markdown(writer, root, 0, with_progress)
     }
 
     fn append_markdown<W: IoWrite>(
@@ -255,20 +1115,321 @@ impl Tree {
         writer: &mut W,
         node_id: NodeId,
         depth: usize,
+        with_progress: bool,
     ) -> io::Result<()> {
-        if let Some(node) = self.nodes.get(&node_id) {
+        if self.nodes.contains_key(&node_id) {
+            // mirrors are written out with the content of their source
+            let node = self.content(node_id);
             write!(writer, "{}- ", "  ".repeat(depth),)?;
 
             if node.struckout {
-                writeln!(writer, "~~{}~~", node.text)?;
+                write!(writer, "~~{}~~", node.text)?;
             } else {
-                writeln!(writer, "{}", node.text)?;
+                write!(writer, "{}", node.text)?;
             }
 
+            if with_progress && !node.children.is_empty() {
+                let (done, total) = self.progress(node_id);
+                write!(writer, " [{done}/{total}]")?;
+            }
+            writeln!(writer)?;
+
             for &child_id in &node.children {
-                self.append_markdown(writer, child_id, depth + 1)?;
+                self.append_markdown(writer, child_id, depth + 1, with_progress)?;
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn clone_mints_new_uids() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        t.add_node("b".into(), a);
+        let c = t.clone_node(a, t.root_id(), None);
+        assert_ne!(t.node(a).uid, t.node(c).uid);
+        assert_ne!(
+            t.node(t.node(a).children[0]).uid,
+            t.node(t.node(c).children[0]).uid
+        );
+    }
+
+    #[test]
+    fn old_trees_get_uids() {
+        let mut t: Tree = ron::from_str(
+            "(next_id: 2, root_id: 1, nodes: { 1: (id: 1, text: \"\", parent: 0, children: []) })",
+        )
+        .unwrap();
+        assert!(t.node(1).uid.is_nil());
+        assert!(t.assign_missing_uids());
+        assert!(!t.node(1).uid.is_nil());
+        assert!(!t.assign_missing_uids());
+        assert_eq!(t.find_by_uid(t.node(1).uid), Some(1));
+    }
+
+    #[test]
+    fn find_by_uid_after_load() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let a_uid = t.node(a).uid;
+        let c = t.clone_node(a, t.root_id(), None);
+        assert_eq!(t.find_by_uid(t.node(c).uid), Some(c));
+
+        let mut loaded: Tree = ron::from_str(&ron::to_string(&t).unwrap()).unwrap();
+        assert_eq!(loaded.find_by_uid(a_uid), None);
+        loaded.index_uids();
+        assert_eq!(loaded.find_by_uid(a_uid), Some(a));
+
+        let mut other = Tree::default();
+        let imported = other.import_subtree(&t, a, other.root_id(), true);
+        assert_eq!(other.find_by_uid(a_uid), Some(imported));
+    }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(natural_cmp("item 2", "item 10"), Ordering::Less);
+        assert_eq!(natural_cmp("item 010", "item 10"), Ordering::Equal);
+        assert_eq!(natural_cmp("b", "a1"), Ordering::Greater);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+    }
+
+    #[test]
+    fn sort_children_by_key() {
+        let mut t = Tree::default();
+        let r = t.root_id();
+        let a = t.add_node("task 10".into(), r);
+        let b = t.add_node("task 9".into(), r);
+        let c = t.add_node("task 1".into(), r);
+        t.node_mut(c).struckout = true;
+        t.sort_children(r, &SortKey::Natural, false, false);
+        assert_eq!(t.node(r).children, vec![c, b, a]);
+        t.sort_children(r, &SortKey::Struckout, false, false);
+        assert_eq!(t.node(r).children, vec![b, a, c]);
+        t.sort_children(r, &SortKey::Text, true, false);
+        assert_eq!(t.node(r).children, vec![b, a, c]);
+    }
+
+    #[test]
+    fn progress_counts_leaves() {
+        let mut t = Tree::default();
+        let r = t.root_id();
+        let a = t.add_node("a".into(), r);
+        let a1 = t.add_node("a1".into(), a);
+        t.add_node("a2".into(), a);
+        let b = t.add_node("b".into(), r);
+        t.add_node("b1".into(), b);
+        t.add_node("b2".into(), b);
+        assert_eq!(t.progress(r), (0, 4));
+        t.node_mut(a1).struckout = true;
+        t.node_mut(b).struckout = true;
+        assert_eq!(t.progress(r), (3, 4));
+        assert_eq!(t.progress(a), (1, 2));
+    }
+
+    #[test]
+    fn numeric_rollup() {
+        let mut t = Tree::default();
+        let r = t.root_id();
+        let a = t.add_node("hosting $120".into(), r);
+        t.add_node("domain 15.5".into(), a);
+        t.add_node("no number".into(), r);
+        let c = t.add_node("labour".into(), r);
+        t.node_mut(c)
+            .properties
+            .insert("value".into(), "300".into());
+        let values = t.leaf_values(r);
+        assert_eq!(Aggregate::Sum.apply(&values), Some(315.5));
+        assert_eq!(Aggregate::Max.apply(&values), Some(300.0));
+        assert_eq!(Aggregate::Min.apply(&[]), None);
+    }
+
+    #[test]
+    fn clocked_time_rolls_up() {
+        let mut t = Tree::default();
+        let r = t.root_id();
+        let a = t.add_node("a".into(), r);
+        let b = t.add_node("b".into(), a);
+        // noon, so that the entries don't cross midnight and get split between days
+        let start = Local
+            .with_ymd_and_hms(2024, 3, 5, 12, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        t.node_mut(a).clock.push(ClockEntry {
+            start,
+            end: Some(start + Duration::hours(1)),
+        });
+        t.node_mut(b).clock.push(ClockEntry {
+            start,
+            end: Some(start + Duration::minutes(30)),
+        });
+        assert_eq!(t.clocked_time(r), Duration::minutes(90));
+        assert_eq!(t.running_clock(), None);
+        assert_eq!(format_duration(t.clocked_time(r)), "1h 30m");
+
+        let m = t.add_mirror(b, r, None);
+        assert_eq!(t.clocked_time(m), Duration::minutes(30));
+        assert_eq!(t.clocked_time(r), Duration::minutes(90));
+        let mut report = Vec::new();
+        t.write_clock_report(&mut report, r).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert_eq!(report.matches("- a > b: 30m").count(), 2);
+
+        t.node_mut(b).clock.push(ClockEntry {
+            start: Utc::now(),
+            end: None,
+        });
+        assert_eq!(t.clock_rollup(r).1, 1);
+        assert_eq!(t.clock_rollup(m).1, 1);
+    }
+
+    #[test]
+    fn template_variables() {
+        let mut t = Tree::default();
+        let r = t.root_id();
+        let a = t.add_node("Meeting {{date}} with {{who}}".into(), r);
+        t.add_node("Notes for {{who}} on {{topic}}".into(), a);
+        assert_eq!(t.template_vars(a), vec!["date", "who", "topic"]);
+        t.substitute_vars(a, &[("who".into(), "Sam".into())]);
+        assert_eq!(t.node(a).text, "Meeting {{date}} with Sam");
+        assert_eq!(t.template_vars(a), vec!["date", "topic"]);
+        t.substitute_vars(
+            a,
+            &[
+                ("date".into(), "{{topic}}".into()),
+                ("topic".into(), "{{date}}".into()),
+            ],
+        );
+        assert_eq!(t.node(a).text, "Meeting {{topic}} with Sam");
+    }
+
+    #[test]
+    fn mirror_shares_content() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let b = t.add_node("b".into(), t.root_id());
+        t.add_node("child".into(), a);
+        let m = t.add_mirror(a, b, None);
+        t.content_mut(m).text = "changed".into();
+        assert_eq!(t.node(a).text, "changed");
+        assert_eq!(t.content(m).children, t.node(a).children);
+    }
+
+    #[test]
+    fn clone_of_mirror_is_mirror() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let m = t.add_mirror(a, t.root_id(), None);
+        let c = t.clone_node(m, t.root_id(), None);
+        assert_eq!(t.node(c).mirror_of, Some(a));
+    }
+
+    #[test]
+    fn matching_subtrees_include_mirrors() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let b = t.add_node("b".into(), a);
+        let other = t.add_node("other".into(), t.root_id());
+        let m = t.add_mirror(a, other, None);
+        let quiet = t.add_node("quiet".into(), t.root_id());
+        let found = t.subtrees_matching(|n| n.text == "b");
+        for n in [t.root_id(), a, b, other, m] {
+            assert!(found.contains(&n));
+        }
+        assert!(!found.contains(&quiet));
+    }
+
+    #[test]
+    fn remove_subtree_for_good() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let b = t.add_node("b".into(), a);
+        let b_uid = t.node(b).uid;
+        let other = t.add_node("other".into(), t.root_id());
+        assert!(!t.mirrored_outside(a));
+        let m = t.add_mirror(b, other, None);
+        assert!(t.mirrored_outside(a));
+        t.cut_node(m);
+        t.remove_subtree(m);
+        assert!(!t.mirrored_outside(a));
+        t.remove_subtree(a);
+        assert!(!t.nodes.contains_key(&a) && !t.nodes.contains_key(&b));
+        assert!(!t.is_attached(b));
+        assert_eq!(t.find_by_uid(b_uid), None);
+        assert_eq!(t.node(t.root_id()).children, vec![other]);
+    }
+
+    #[test]
+    fn mirror_cycle_detected() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let b = t.add_node("b".into(), a);
+        assert!(t.would_create_cycle(a, b));
+        let m = t.add_mirror(a, t.root_id(), None);
+        assert!(t.would_create_cycle(m, b));
+        assert!(!t.would_create_cycle(b, t.root_id()));
+    }
+
+    #[test]
+    fn dependencies() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let b = t.add_node("b".into(), t.root_id());
+        let c = t.add_node("c".into(), b);
+        let b_uid = t.node(b).uid;
+        t.node_mut(a).blocked_by.push(b_uid);
+        assert!(t.is_blocked(a));
+        assert_eq!(t.actionable_leaves(t.root_id()), vec![c]);
+        assert_eq!(t.actionable_leaves(b), vec![c]);
+        assert!(t.would_create_dependency_cycle(b, a));
+        assert!(t.would_create_dependency_cycle(a, a));
+        assert!(!t.would_create_dependency_cycle(c, a));
+        t.node_mut(b).struckout = true;
+        assert!(!t.is_blocked(a));
+        assert_eq!(t.actionable_leaves(t.root_id()), vec![a]);
+
+        let d = t.add_node("d".into(), t.root_id());
+        let d_uid = t.node(d).uid;
+        t.node_mut(b).blocked_by.push(d_uid);
+        assert!(!t.redirect_would_create_dependency_cycle(b, d));
+        t.redirect_dependencies(b, d);
+        assert_eq!(t.node(a).blocked_by, vec![d_uid]);
+        assert!(t.node(d).blocked_by.is_empty());
+
+        // joining `c` into `a` would leave `a` waiting on `d`, which waits on `a`
+        let c_uid = t.node(c).uid;
+        t.node_mut(d).blocked_by.push(c_uid);
+        assert!(t.redirect_would_create_dependency_cycle(c, a));
+    }
+
+    #[test]
+    fn sort_selected_nodes() {
+        let mut t = Tree::default();
+        let r = t.root_id();
+        let c = t.add_node("c".into(), r);
+        let x = t.add_node("x".into(), r);
+        let b = t.add_node("b".into(), r);
+        let a = t.add_node("a".into(), r);
+        t.sort_nodes(&[c, b, a], &SortKey::Text, false);
+        assert_eq!(t.node(r).children, vec![a, x, b, c]);
+        let a1 = t.add_node("a1".into(), a);
+        assert_eq!(t.top_level_in_order(&[c, a1, a, x]), vec![a, x, c]);
+    }
+
+    #[test]
+    fn marks() {
+        let mut t = Tree::default();
+        let a = t.add_node("a".into(), t.root_id());
+        let b = t.add_node("b".into(), a);
+        t.set_mark('x', b);
+        assert_eq!(t.mark('x'), Some(b));
+        assert_eq!(t.mark('y'), None);
+        t.cut_node(a);
+        assert_eq!(t.mark('x'), None);
+    }
+}
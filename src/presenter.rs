@@ -1,18 +1,177 @@
 use crate::{
-    model::{NodeId, Tree, ROOT_PARENT_ID},
+    config::Config,
+    model::{Aggregate, ClockEntry, NodeId, SortKey, TimeFilter, Tree, ROOT_PARENT_ID},
+    recurrence::Recurrence,
     storage::{self, Storage},
 };
 
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
 use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use smallvec::SmallVec;
+use uuid::Uuid;
+
+/// Property marking the node that archived nodes are moved under.
+const ARCHIVE_PROPERTY: &str = "archive";
+/// Property recording where an archived node was archived from.
+const ARCHIVED_FROM_PROPERTY: &str = "archived_from";
+
+/// Property naming a subtree that can be instantiated as a template.
+const TEMPLATE_PROPERTY: &str = "template";
+
+/// Property holding the rule a recurring node repeats by.
+const REPEAT_PROPERTY: &str = "repeat";
+/// Property holding the date a node is due, as `YYYY-MM-DD`.
+const DATE_PROPERTY: &str = "date";
+
+/// Property marking the node that the journal is kept under.
+const JOURNAL_PROPERTY: &str = "journal";
+
+/// A question asked on the command line. The next command entered is taken as the answer.
+enum Prompt {
+    /// Fill in the variables in `remaining`, one at a time, in a template that was just
+    /// instantiated at `node`. The text is only changed once all of them have values.
+    TemplateVars {
+        node: NodeId,
+        values: Vec<(String, String)>,
+        remaining: Vec<String>,
+    },
+}
+
+/// What to do with the node chosen in the picker.
+pub enum PickAction {
+    /// Move `nodes` under the chosen node, in the tree stored at `dest` if given.
+    Refile {
+        nodes: Vec<NodeId>,
+        dest: Option<String>,
+    },
+    /// Move the cursor to the chosen node.
+    Jump,
+}
+
+/// A request from a command for the user to choose a node.
+pub struct PickRequest {
+    pub action: PickAction,
+    /// Each node that can be chosen, with the text to show and match against.
+    pub items: Vec<(NodeId, String)>,
+}
+
+/// The totals shown next to a node that cover the nodes below it.
+#[derive(Clone, Copy)]
+pub struct Rollup {
+    /// How many of the leaves below are struck out, and how many there are.
+    pub progress: (usize, usize),
+    /// The values below combined into one, and how they were combined.
+    pub value: Option<(Aggregate, f64)>,
+    clocked: Duration,
+    running_clocks: usize,
+    at: DateTime<Utc>,
+}
+
+impl Rollup {
+    /// Total time clocked on the node and the nodes below it, up to now.
+    pub fn clocked_time(&self) -> Duration {
+        self.clocked + (Utc::now() - self.at) * self.running_clocks as i32
+    }
+}
+
+/// Subtrees on the snip stack, along with the nodes they were cut or copied from. Usually this is
+/// a single node, but a selection is snipped all together.
+struct NodeSnip {
+    nodes: SmallVec<[NodeId; 1]>,
+    origins: SmallVec<[NodeId; 1]>,
+}
+
+/// A stack of snips, along with named registers that hold one snip each. Commands choose where
+/// their snip goes or comes from with a register: a letter names a register, a digit an entry on
+/// the stack counting down from `1` at the top, and no register means the top of the stack.
+struct Snips<T> {
+    stack: Vec<T>,
+    registers: BTreeMap<char, T>,
+}
+
+impl<T> Snips<T> {
+    fn new() -> Snips<T> {
+        Snips {
+            stack: Vec::new(),
+            registers: BTreeMap::new(),
+        }
+    }
+
+    /// Where on the stack the entry `depth` down from the top is.
+    fn index(&self, depth: u32) -> Option<usize> {
+        self.stack
+            .len()
+            .checked_sub(depth as usize)
+            .filter(|_| depth > 0)
+    }
+
+    /// Keep `snip` in `reg`. Snips put in a numbered register go on top of the stack, as they do
+    /// without a register.
+    fn store(&mut self, reg: Option<char>, snip: T) {
+        match reg {
+            Some(r) if !r.is_ascii_digit() => {
+                self.registers.insert(r, snip);
+            }
+            _ => self.stack.push(snip),
+        }
+    }
+
+    fn get(&self, reg: Option<char>) -> Option<&T> {
+        match reg.map(|r| (r, r.to_digit(10))) {
+            None => self.stack.last(),
+            Some((_, Some(depth))) => self.index(depth).map(|i| &self.stack[i]),
+            Some((r, None)) => self.registers.get(&r),
+        }
+    }
+
+    fn take(&mut self, reg: Option<char>) -> Option<T> {
+        match reg.map(|r| (r, r.to_digit(10))) {
+            None => self.stack.pop(),
+            Some((_, Some(depth))) => self.index(depth).map(|i| self.stack.remove(i)),
+            Some((r, None)) => self.registers.remove(&r),
+        }
+    }
+
+    /// Describe each snip, the stack from the top down and then the registers.
+    fn describe(&self, describe: impl Fn(&T) -> String) -> Vec<String> {
+        let stack = self.stack.iter().rev().enumerate();
+        stack
+            .map(|(i, s)| format!("{}: {}", i + 1, describe(s)))
+            .chain(
+                self.registers
+                    .iter()
+                    .map(|(r, s)| format!("{r}: {}", describe(s))),
+            )
+            .collect()
+    }
+}
 
 pub struct Presenter {
     tree: Tree,
     storage: Option<Box<dyn Storage>>,
-    current_root: NodeId,
-    snip_stack_nodes: Vec<NodeId>,
-    snip_stack_strs: Vec<String>,
+    /// The subtrees the view has been narrowed to, innermost last. Empty when showing the whole
+    /// tree.
+    root_stack: Vec<NodeId>,
+    snip_stack_nodes: Snips<NodeSnip>,
+    snip_stack_strs: Snips<String>,
     tree_modified: bool,
     should_exit: bool,
+    config: Config,
+    filter: Option<TimeFilter>,
+    /// The nodes the filter lets through, worked out when the tree was at the given generation.
+    visible: RefCell<Option<(u64, HashSet<NodeId>)>>,
+    /// The totals shown next to nodes, worked out when the tree was at the given generation.
+    rollups: RefCell<Option<(u64, HashMap<NodeId, Rollup>)>>,
+    /// The unique ID of the node being clocked, which stays valid as nodes move around.
+    clocked_in: Option<Uuid>,
+    prompt: Option<Prompt>,
+    message: Option<String>,
+    pick_request: Option<PickRequest>,
 }
 
 impl Presenter {
@@ -26,15 +185,30 @@ impl Presenter {
             (Tree::default(), None)
         };
 
-        Ok(Presenter {
-            current_root: tree.root_id(),
+        let mut p = Presenter::with_tree(tree, storage, Config::load()?);
+        p.sync_inbox()?;
+        Ok(p)
+    }
+
+    /// A presenter showing the whole of `tree`, which is synced to `storage` if given.
+    fn with_tree(tree: Tree, storage: Option<Box<dyn Storage>>, config: Config) -> Presenter {
+        Presenter {
+            root_stack: Vec::new(),
+            clocked_in: tree.running_clock().map(|n| tree.node(n).uid),
             tree,
             storage,
-            snip_stack_nodes: Vec::new(),
-            snip_stack_strs: Vec::new(),
+            snip_stack_nodes: Snips::new(),
+            snip_stack_strs: Snips::new(),
             should_exit: false,
             tree_modified: false,
-        })
+            config,
+            filter: None,
+            visible: RefCell::new(None),
+            rollups: RefCell::new(None),
+            prompt: None,
+            message: None,
+            pick_request: None,
+        }
     }
 
     pub fn storage_name(&self) -> Option<String> {
@@ -46,7 +220,10 @@ impl Presenter {
     }
 
     pub fn current_root(&self) -> NodeId {
-        self.current_root
+        self.root_stack
+            .last()
+            .copied()
+            .unwrap_or_else(|| self.tree.root_id())
     }
 
     pub fn should_exit(&self) -> bool {
@@ -57,9 +234,140 @@ impl Presenter {
         self.tree_modified
     }
 
-    pub fn set_current_root(&mut self, new_root: NodeId) {
+    /// The question being asked on the command line, if any.
+    pub fn prompt(&self) -> Option<String> {
+        self.prompt.as_ref().map(|p| match p {
+            Prompt::TemplateVars { remaining, .. } => format!("{}:", remaining[0]),
+        })
+    }
+
+    pub fn cancel_prompt(&mut self) {
+        // keep the values given so far, leaving the rest of the variables as they are
+        if let Some(Prompt::TemplateVars { node, values, .. }) = self.prompt.take() {
+            self.tree.substitute_vars(node, &values);
+        }
+    }
+
+    /// Output from the last command to show to the user, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn clear_message(&mut self) {
+        self.message = None;
+    }
+
+    /// Take the request for the user to choose a node made by the last command, if any.
+    pub fn take_pick_request(&mut self) -> Option<PickRequest> {
+        self.pick_request.take()
+    }
+
+    fn answer_prompt(&mut self, prompt: Prompt, answer: String) -> Result<Option<NodeId>> {
+        match prompt {
+            Prompt::TemplateVars {
+                node,
+                mut values,
+                mut remaining,
+            } => {
+                values.push((remaining.remove(0), answer));
+                self.fill_template_vars(node, values, remaining);
+                Ok(None)
+            }
+        }
+    }
+
+    /// The node that is currently being clocked, if any.
+    pub fn clocked_in(&self) -> Option<NodeId> {
+        self.clocked_in.and_then(|uid| self.tree.find_by_uid(uid))
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Store the keys of a recorded macro, replacing any it had before.
+    pub fn set_macro(&mut self, reg: char, keys: String) {
+        self.config.macros.insert(reg, keys);
+    }
+
+    /// Check if a node should be shown given the current filter. Nodes are shown if they or any
+    /// of their descendants match.
+    pub fn node_visible(&self, node: NodeId) -> bool {
+        let f = match self.filter {
+            Some(f) => f,
+            None => return true,
+        };
+        let mut visible = self.visible.borrow_mut();
+        let generation = self.tree.generation();
+        if !matches!(&*visible, Some((g, _)) if *g == generation) {
+            *visible = Some((generation, self.tree.subtrees_matching(|n| f.matches(n))));
+        }
+        visible.as_ref().is_some_and(|(_, v)| v.contains(&node))
+    }
+
+    /// The totals shown next to `node`, which are only worked out again once the tree changes.
+    pub fn rollup(&self, node: NodeId) -> Rollup {
+        let mut rollups = self.rollups.borrow_mut();
+        let generation = self.tree.generation();
+        if !matches!(&*rollups, Some((g, _)) if *g == generation) {
+            *rollups = Some((generation, HashMap::new()));
+        }
+        let (_, rollups) = rollups.as_mut().unwrap();
+        *rollups.entry(node).or_insert_with(|| {
+            let value = self
+                .tree
+                .rollup_aggregate(node)
+                .unwrap_or(self.config.rollup)
+                .and_then(|agg| Some((agg, agg.apply(&self.tree.leaf_values(node))?)));
+            let (clocked, running_clocks) = self.tree.clock_rollup(node);
+            Rollup {
+                progress: self.tree.progress(node),
+                value,
+                clocked,
+                running_clocks,
+                at: Utc::now(),
+            }
+        })
+    }
+
+    /// The closest node to `node` that the filter shows, going up the tree.
+    fn nearest_visible(&self, node: NodeId) -> NodeId {
+        self.tree
+            .ancestors(node)
+            .take_while(|n| *n != self.current_root())
+            .find(|n| self.node_visible(*n))
+            .unwrap_or_else(|| self.current_root())
+    }
+
+    /// Narrow the view to the subtree under `new_root`.
+    pub fn narrow(&mut self, new_root: NodeId) {
         assert!(self.tree.nodes.contains_key(&new_root));
-        self.current_root = new_root;
+        if new_root != self.current_root() {
+            self.root_stack.push(new_root);
+        }
+    }
+
+    /// Go back to the view from before the last `narrow`, returning the root that was left.
+    pub fn widen(&mut self) -> Option<NodeId> {
+        self.root_stack.pop()
+    }
+
+    /// Go back through narrowed views until `node` is inside the current one.
+    pub fn widen_to(&mut self, node: NodeId) {
+        let ancestors: Vec<NodeId> = self.tree.ancestors(node).collect();
+        while !ancestors.contains(&self.current_root()) && self.root_stack.pop().is_some() {}
+    }
+
+    /// Show `new_root` as the root, keeping the narrowed views it is inside of.
+    pub fn set_current_root(&mut self, new_root: NodeId) {
+        self.widen_to(new_root);
+        self.narrow(new_root);
+    }
+
+    /// Stop narrowing to any subtrees that are no longer part of the tree.
+    fn drop_detached_roots(&mut self) {
+        let tree = &self.tree;
+        self.root_stack.retain(|n| tree.is_attached(*n));
     }
 
     pub fn insert_node_in_parent(
@@ -80,6 +388,7 @@ impl Presenter {
 
     pub fn insert_node_as_child(&mut self, cur_node: NodeId, prepend_or_append: bool) -> NodeId {
         self.tree_modified = true;
+        let cur_node = self.tree.source(cur_node);
         if prepend_or_append {
             self.tree.add_node_at_beginning(String::new(), cur_node)
         } else {
@@ -87,74 +396,717 @@ impl Presenter {
         }
     }
 
+    /// Where the cursor should go when `nodes` are removed from the tree: the sibling after the
+    /// last of them, or otherwise the parent of the first.
+    fn cursor_after_removing(&self, nodes: &[NodeId]) -> Option<NodeId> {
+        let next = nodes
+            .last()
+            .and_then(|n| self.tree.next_child(*n))
+            .filter(|n| !nodes.contains(n));
+        next.or_else(|| nodes.first().and_then(|n| self.tree.node(*n).parent()))
+    }
+
     pub fn delete_node(&mut self, cur_node: NodeId) -> Option<NodeId> {
+        self.delete_nodes(&[cur_node], None)
+    }
+
+    /// Cut `nodes` out of the tree into register `reg` as one entry. Returns where the cursor
+    /// should go, or None if nothing could be cut.
+    pub fn delete_nodes(&mut self, nodes: &[NodeId], reg: Option<char>) -> Option<NodeId> {
+        let nodes: SmallVec<[NodeId; 1]> = nodes
+            .iter()
+            .copied()
+            .filter(|n| self.tree.node(*n).parent().is_some())
+            .collect();
+        if nodes.is_empty() {
+            return None;
+        }
         self.tree_modified = true;
-        let p = self.tree.node(cur_node).parent();
-        if p.is_some() {
-            self.tree.cut_node(cur_node);
-            self.snip_stack_nodes.push(cur_node);
-            p
-        } else {
-            None
+        let new_cursor = self.cursor_after_removing(&nodes);
+        for n in nodes.iter() {
+            self.tree.cut_node(*n);
         }
+        self.snip_stack_nodes.store(
+            reg,
+            NodeSnip {
+                origins: nodes.clone(),
+                nodes,
+            },
+        );
+        self.drop_detached_roots();
+        new_cursor
     }
 
     pub fn copy_node(&mut self, cur_node: NodeId) {
-        self.snip_stack_nodes
-            .push(self.tree.clone_node(cur_node, ROOT_PARENT_ID, None));
+        self.copy_nodes(&[cur_node], None);
     }
 
-    fn move_or_clone_node_from_top_of_snips(
+    /// Copy `nodes` into register `reg` as one entry.
+    pub fn copy_nodes(&mut self, nodes: &[NodeId], reg: Option<char>) {
+        let copies = nodes
+            .iter()
+            .map(|n| self.tree.clone_node(*n, ROOT_PARENT_ID, None))
+            .collect();
+        self.snip_stack_nodes.store(
+            reg,
+            NodeSnip {
+                nodes: copies,
+                origins: nodes.iter().copied().collect(),
+            },
+        );
+    }
+
+    /// Put the nodes in register `reg` into `parent`, after `after` or at the end. Returns the
+    /// first node put.
+    fn move_or_clone_node_from_snips(
         &mut self,
+        reg: Option<char>,
         consume: bool,
         parent: NodeId,
-        after: Option<NodeId>,
+        mut after: Option<NodeId>,
     ) -> Option<NodeId> {
+        let nodes = self.snip_stack_nodes.get(reg)?.nodes.clone();
+        if nodes
+            .iter()
+            .any(|n| self.tree.would_create_cycle(*n, parent))
+        {
+            return None;
+        }
         if consume {
-            self.snip_stack_nodes.pop().map(|n| {
+            self.snip_stack_nodes.take(reg);
+        }
+        let mut first = None;
+        for n in nodes {
+            let put = if consume {
                 self.tree
                     .reparent_node(n, parent, after.map(|n| (n, false)));
+                self.tree.node_mut(n).touch();
                 n
-            })
-        } else {
-            self.snip_stack_nodes
-                .last()
-                .map(|n| self.tree.clone_node(*n, parent, after))
+            } else {
+                self.tree.clone_node(n, parent, after)
+            };
+            first = first.or(Some(put));
+            after = after.map(|_| put);
         }
+        first
     }
 
-    pub fn put_node(&mut self, cur_node: NodeId, consume: bool, as_child: bool) -> Option<NodeId> {
+    pub fn put_node(
+        &mut self,
+        cur_node: NodeId,
+        reg: Option<char>,
+        consume: bool,
+        as_child: bool,
+    ) -> Option<NodeId> {
         self.tree_modified = true;
         if !as_child && self.tree.node(cur_node).parent != ROOT_PARENT_ID {
             let p = self.tree.node(cur_node).parent;
-            self.move_or_clone_node_from_top_of_snips(consume, p, Some(cur_node))
+            self.move_or_clone_node_from_snips(reg, consume, p, Some(cur_node))
         } else {
-            self.move_or_clone_node_from_top_of_snips(consume, cur_node, None)
+            let cur_node = self.tree.source(cur_node);
+            self.move_or_clone_node_from_snips(reg, consume, cur_node, None)
         }
     }
 
+    /// Put mirrors of the nodes that the snip in register `reg` was cut or copied from. Returns
+    /// the first mirror.
+    pub fn put_mirror(
+        &mut self,
+        cur_node: NodeId,
+        reg: Option<char>,
+        as_child: bool,
+    ) -> Result<NodeId> {
+        let origins = self
+            .snip_stack_nodes
+            .get(reg)
+            .context("nothing to put")?
+            .origins
+            .clone();
+        if origins.iter().any(|o| !self.tree.nodes.contains_key(o)) {
+            anyhow::bail!("the snipped nodes were moved to another tree");
+        }
+        let (parent, mut after) = if !as_child && self.tree.node(cur_node).parent != ROOT_PARENT_ID
+        {
+            (self.tree.node(cur_node).parent, Some(cur_node))
+        } else {
+            (self.tree.source(cur_node), None)
+        };
+        if origins
+            .iter()
+            .any(|o| self.tree.would_create_cycle(*o, parent))
+        {
+            anyhow::bail!("can't mirror a node inside itself");
+        }
+        self.tree_modified = true;
+        let mut first = None;
+        for origin in origins {
+            let m = self.tree.add_mirror(origin, parent, after);
+            first = first.or(Some(m));
+            after = after.map(|_| m);
+        }
+        first.context("snip stack is empty")
+    }
+
     pub fn swap_node(&mut self, cur_node: NodeId, direction: isize) {
         self.tree.swap_node(cur_node, direction);
+        self.tree.node_mut(cur_node).touch();
         self.tree_modified = true;
     }
 
     pub fn update_node_text(&mut self, cur_node: usize, new_text: String) {
-        self.tree.node_mut(cur_node).text = new_text;
+        let node = self.tree.content_mut(cur_node);
+        node.text = new_text;
+        node.touch();
+        self.tree_modified = true;
+    }
+
+    /// Strike out `cur_node`, or un-strike it if it was already struck out. Recurring nodes
+    /// are never left struck out; instead the completion is recorded and their date moves on to
+    /// the next time they come around.
+    pub fn toggle_strikeout(&mut self, cur_node: usize) -> Result<()> {
+        let node = self.tree.content_mut(cur_node);
+        match node.properties.get(REPEAT_PROPERTY) {
+            Some(rule) if !node.struckout => {
+                let rule: Recurrence = rule.parse()?;
+                let today = chrono::Local::now().date_naive();
+                let date = match node.properties.get(DATE_PROPERTY) {
+                    Some(d) => chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                        .with_context(|| format!("invalid date: {d}"))?,
+                    None => today,
+                };
+                let next = rule.next_after(date.max(today));
+                node.completions.push(chrono::Utc::now());
+                node.properties
+                    .insert(DATE_PROPERTY.into(), next.format("%Y-%m-%d").to_string());
+            }
+            _ => {
+                node.struckout = !node.struckout;
+                node.auto_struck = false;
+            }
+        }
+        node.touch();
+        if self.config.auto_strike_parents {
+            self.update_parent_strikeout(self.tree.source(cur_node));
+        }
+        self.tree_modified = true;
+        Ok(())
+    }
+
+    /// Strike out each ancestor of `node` whose children are all struck out, and un-strike any
+    /// that no longer are if they were struck out this way rather than by hand.
+    fn update_parent_strikeout(&mut self, node: NodeId) {
+        let mut cur = self.tree.node(node).parent();
+        while let Some(p) = cur {
+            let all_done = self
+                .tree
+                .node(p)
+                .children
+                .iter()
+                .all(|c| self.tree.content(*c).struckout);
+            let parent = self.tree.node_mut(p);
+            if all_done == parent.struckout || (!all_done && !parent.auto_struck) {
+                break;
+            }
+            parent.struckout = all_done;
+            parent.auto_struck = all_done;
+            parent.touch();
+            cur = parent.parent();
+        }
+    }
+
+    /// Start the clock on `node`, stopping the clock that was already running, or just stop the
+    /// clock if it was running on `node`.
+    pub fn toggle_clock(&mut self, node: NodeId) {
+        let node = self.tree.source(node);
+        let now = chrono::Utc::now();
+        self.tree_modified = true;
+        if let Some(running) = self.clocked_in() {
+            self.clocked_in = None;
+            if let Some(entry) = self.tree.node_mut(running).clock.last_mut() {
+                entry.end = Some(now);
+            }
+            if running == node {
+                return;
+            }
+        }
+        self.tree.node_mut(node).clock.push(ClockEntry {
+            start: now,
+            end: None,
+        });
+        self.clocked_in = Some(self.tree.node(node).uid);
+    }
+
+    /// Stop the clock if it is running on one of `nodes` or a node below them, before they move
+    /// to another tree.
+    fn stop_clock_below(&mut self, nodes: &[NodeId]) {
+        if let Some(running) = self.clocked_in() {
+            if self.tree.ancestors(running).any(|n| nodes.contains(&n)) {
+                self.toggle_clock(running);
+            }
+        }
+    }
+
+    /// Copy the template named `name` to be the last child of `cur_node`. Templates are subtrees
+    /// with a `template` property, either in this tree or in the tree stored at the location in
+    /// the `templates` setting. `{{date}}`, `{{time}}` and `{{title}}` (the text of `cur_node`) are
+    /// filled in, along with any variables given in `vars`. The user is prompted for the rest.
+    pub fn instantiate_template(
+        &mut self,
+        cur_node: NodeId,
+        name: &str,
+        vars: &[(&str, &str)],
+    ) -> Result<NodeId> {
+        let parent = self.tree.source(cur_node);
+        let new_node = if let Some(t) = self.tree.find_with_property_value(TEMPLATE_PROPERTY, name)
+        {
+            // the copy is made outside the tree, since `parent` may be inside the template
+            let copy = self.tree.clone_node(t, ROOT_PARENT_ID, None);
+            self.tree.reparent_node(copy, parent, None);
+            copy
+        } else {
+            let url = self
+                .config
+                .templates
+                .as_deref()
+                .with_context(|| format!("no template named {name}"))?;
+            let (templates, _) = storage::open_storage(url).context("open templates")?;
+            let templates = templates.unwrap_or_default();
+            let t = templates
+                .find_with_property_value(TEMPLATE_PROPERTY, name)
+                .with_context(|| format!("no template named {name}"))?;
+            self.tree.import_subtree(&templates, t, parent, false)
+        };
+        self.tree
+            .node_mut(new_node)
+            .properties
+            .remove(TEMPLATE_PROPERTY);
+
+        let now = chrono::Local::now();
+        let title = self
+            .tree
+            .content(cur_node)
+            .text
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_owned();
+        let mut values = vec![
+            ("date".to_owned(), now.format("%Y-%m-%d").to_string()),
+            ("time".to_owned(), now.format("%H:%M").to_string()),
+            ("title".to_owned(), title),
+        ];
+        values.extend(vars.iter().map(|(n, v)| (n.to_string(), v.to_string())));
+        let remaining = self
+            .tree
+            .template_vars(new_node)
+            .into_iter()
+            .filter(|v| !values.iter().any(|(n, _)| n == v))
+            .collect();
+        self.fill_template_vars(new_node, values, remaining);
+        self.tree_modified = true;
+        Ok(new_node)
+    }
+
+    /// Substitute `values` into the template at `node`, or prompt for the first of `remaining` if
+    /// there are still variables without a value.
+    fn fill_template_vars(
+        &mut self,
+        node: NodeId,
+        values: Vec<(String, String)>,
+        remaining: Vec<String>,
+    ) {
+        if remaining.is_empty() {
+            self.tree.substitute_vars(node, &values);
+        } else {
+            self.prompt = Some(Prompt::TemplateVars {
+                node,
+                values,
+                remaining,
+            });
+        }
+    }
+
+    /// Reorder the children of `node` by `key` as a single change to the tree.
+    pub fn sort_children(
+        &mut self,
+        node: NodeId,
+        key: &SortKey,
+        descending: bool,
+        recursive: bool,
+    ) {
+        self.tree.sort_children(node, key, descending, recursive);
+        self.tree.content_mut(node).touch();
+        self.tree_modified = true;
+    }
+
+    /// Sort `nodes` among themselves within their parents.
+    pub fn sort_nodes(&mut self, nodes: &[NodeId], key: &SortKey, descending: bool) {
+        self.tree.sort_nodes(nodes, key, descending);
+        self.tree_modified = true;
+    }
+
+    /// Make `node` the last child of its previous sibling.
+    pub fn indent_node(&mut self, node: NodeId) {
+        if let Some(prev) = self.tree.prev_child(node) {
+            if !self.tree.would_create_cycle(node, prev) {
+                self.tree.reparent_node(node, self.tree.source(prev), None);
+                self.tree.node_mut(node).touch();
+                self.tree_modified = true;
+            }
+        }
+    }
+
+    /// Set a property on a node, or remove it if `value` is None.
+    pub fn set_property(&mut self, node: NodeId, key: &str, value: Option<String>) {
+        let node = self.tree.content_mut(node);
+        match value {
+            Some(v) => node.properties.insert(key.to_owned(), v),
+            None => node.properties.remove(key),
+        };
+        node.touch();
+        self.tree_modified = true;
+    }
+
+    /// Mark `node` as `name`, so that it can be jumped back to.
+    pub fn set_mark(&mut self, name: char, node: NodeId) {
+        self.tree.set_mark(name, node);
         self.tree_modified = true;
     }
 
-    pub fn toggle_strikeout(&mut self, cur_node: usize) {
-        let node = self.tree.node_mut(cur_node);
-        node.struckout = !node.struckout;
+    /// Find the node that `query` refers to, either by its unique ID or by a piece of the first
+    /// line of its text, which must only match one node.
+    fn find_node(&self, query: &str) -> Result<NodeId> {
+        if let Ok(uid) = uuid::Uuid::parse_str(query) {
+            return self
+                .tree
+                .find_by_uid(uid)
+                .with_context(|| format!("no node with ID {uid}"));
+        }
+        let query = query.to_lowercase();
+        let mut matches = self.tree.nodes.values().filter(|n| {
+            n.mirror_of.is_none()
+                && n.text
+                    .lines()
+                    .next()
+                    .is_some_and(|l| l.to_lowercase().contains(&query))
+                && self.tree.is_attached(n.id)
+        });
+        match (matches.next(), matches.next()) {
+            (Some(n), None) => Ok(n.id),
+            (Some(_), Some(_)) => anyhow::bail!("more than one node matches \"{query}\""),
+            (None, _) => anyhow::bail!("no node matches \"{query}\""),
+        }
+    }
+
+    /// Make `node` wait on `blocker` being struck out.
+    pub fn add_blocker(&mut self, node: NodeId, blocker: NodeId) -> Result<()> {
+        if self.tree.would_create_dependency_cycle(node, blocker) {
+            anyhow::bail!("nodes can't wait on each other");
+        }
+        let uid = self.tree.content(blocker).uid;
+        let node = self.tree.content_mut(node);
+        if !node.blocked_by.contains(&uid) {
+            node.blocked_by.push(uid);
+            node.touch();
+            self.tree_modified = true;
+        }
+        Ok(())
+    }
+
+    pub fn clear_blockers(&mut self, node: NodeId) {
+        let node = self.tree.content_mut(node);
+        node.blocked_by.clear();
+        node.touch();
+        self.tree_modified = true;
+    }
+
+    /// Find the top-most struck out nodes in the tree, ignoring anything already archived.
+    fn completed_subtrees(&self) -> Vec<NodeId> {
+        let mut completed = Vec::new();
+        let mut stack = vec![self.tree.root_id()];
+        while let Some(n) = stack.pop() {
+            let node = self.tree.node(n);
+            if node.properties.contains_key(ARCHIVE_PROPERTY) {
+                continue;
+            }
+            if n != self.tree.root_id() && self.tree.content(n).struckout {
+                completed.push(n);
+            } else if node.mirror_of.is_none() {
+                stack.extend(node.children.iter().rev());
+            }
+        }
+        completed
+    }
+
+    /// Find the archive node, creating one at the end of the tree if there isn't one yet.
+    fn archive_node(&mut self) -> NodeId {
+        self.tree
+            .find_with_property(ARCHIVE_PROPERTY)
+            .unwrap_or_else(|| {
+                let n = self.tree.add_node("Archive".into(), self.tree.root_id());
+                self.tree
+                    .node_mut(n)
+                    .properties
+                    .insert(ARCHIVE_PROPERTY.into(), "true".into());
+                n
+            })
+    }
+
+    /// Find the child of `parent` whose first line is `text`, or add one, keeping the children in
+    /// order by their text.
+    fn find_or_add_child(&mut self, parent: NodeId, text: String) -> NodeId {
+        let parent = self.tree.source(parent);
+        let children = self.tree.node(parent).children.clone();
+        let first_line = |n: &NodeId| self.tree.content(*n).text.lines().next().unwrap_or("");
+        if let Some(c) = children.iter().find(|c| first_line(c) == text) {
+            return *c;
+        }
+        self.tree_modified = true;
+        match children.iter().find(|c| first_line(c) > text.as_str()) {
+            Some(next) => self.tree.insert_node(text, parent, *next, false),
+            None => self.tree.add_node(text, parent),
+        }
+    }
+
+    /// Find or create the journal entry for `date` under the node with the `journal` property,
+    /// which is created at the end of the tree if there isn't one yet. The entry becomes the
+    /// current root, and the new node to write in is returned.
+    pub fn journal(&mut self, date: chrono::NaiveDate) -> NodeId {
+        let root = self
+            .tree
+            .find_with_property(JOURNAL_PROPERTY)
+            .unwrap_or_else(|| {
+                let n = self.tree.add_node("Journal".into(), self.tree.root_id());
+                self.tree
+                    .node_mut(n)
+                    .properties
+                    .insert(JOURNAL_PROPERTY.into(), "true".into());
+                n
+            });
+        let year = self.find_or_add_child(root, date.format("%Y").to_string());
+        let month = self.find_or_add_child(year, date.format("%m %B").to_string());
+        let day = self.find_or_add_child(month, date.format("%Y-%m-%d %A").to_string());
+        self.set_current_root(day);
+
+        // reuse an entry that was never written in
+        let day = self.tree.source(day);
+        if let Some(last) = self.tree.node(day).children.last() {
+            if self.tree.content(*last).text.is_empty() {
+                return *last;
+            }
+        }
+        self.tree_modified = true;
+        self.tree.add_node(String::new(), day)
+    }
+
+    /// Open the tree stored at `dest` to move nodes into. The tree being edited isn't allowed,
+    /// since the next sync would write over whatever was moved into it.
+    fn open_other_storage(&self, dest: &str) -> Result<(Option<Tree>, Box<dyn Storage>)> {
+        let (tree, storage) = storage::open_storage(dest)?;
+        if self
+            .storage
+            .as_ref()
+            .is_some_and(|s| s.same_as(storage.as_ref()))
+        {
+            anyhow::bail!("{dest} is the tree being edited");
+        }
+        Ok((tree, storage))
+    }
+
+    /// Move `nodes` out of this tree for good once they have been copied into another one.
+    fn remove_moved_nodes(&mut self, nodes: &[NodeId]) {
+        for n in nodes {
+            self.tree.remove_subtree(*n);
+        }
+    }
+
+    /// Move `nodes` to the archive, recording the path each was archived from as a property. The
+    /// archive is the node with the `archive` property in this tree, or in the tree stored at
+    /// `dest` if given.
+    pub fn archive(&mut self, nodes: &[NodeId], dest: Option<&str>) -> Result<()> {
+        if nodes.contains(&self.tree.root_id()) {
+            anyhow::bail!("can't archive the root of the tree");
+        }
+        if dest.is_some() && nodes.iter().any(|n| self.tree.mirrored_outside(*n)) {
+            anyhow::bail!("can't move nodes that are mirrored elsewhere to another tree");
+        }
+        if dest.is_none() {
+            if let Some(a) = self.tree.find_with_property(ARCHIVE_PROPERTY) {
+                if self.tree.ancestors(a).any(|n| nodes.contains(&n)) {
+                    anyhow::bail!("can't move the archive into itself");
+                }
+            }
+        }
+
+        // open the destination first so that nothing is changed if it can't be used
+        let other = dest.map(|d| self.open_other_storage(d)).transpose()?;
+
+        for n in nodes {
+            let path = self
+                .tree
+                .node(*n)
+                .parent()
+                .map(|p| self.tree.path_text(p))
+                .unwrap_or_default();
+            let node = self.tree.node_mut(*n);
+            node.properties.insert(ARCHIVED_FROM_PROPERTY.into(), path);
+            node.touch();
+        }
+
+        if let Some((archive_tree, mut archive_storage)) = other {
+            self.stop_clock_below(nodes);
+            let mut archive_tree = archive_tree.unwrap_or_default();
+            let archive_node = archive_tree
+                .find_with_property(ARCHIVE_PROPERTY)
+                .unwrap_or(archive_tree.root_id());
+            for n in nodes {
+                archive_tree.import_subtree(&self.tree, *n, archive_node, true);
+            }
+            if let Some(m) = archive_storage
+                .sync(&mut archive_tree)
+                .context("sync archive")?
+            {
+                self.message = Some(m);
+            }
+            self.remove_moved_nodes(nodes);
+        } else {
+            let archive_node = self.archive_node();
+            for n in nodes {
+                self.tree.reparent_node(*n, archive_node, None);
+            }
+        }
+
+        self.drop_detached_roots();
+        self.tree_modified = true;
+        Ok(())
+    }
+
+    /// Move `nodes` to be the last children of `target`, or into the tree stored at `dest` if
+    /// given. Returns where the cursor should go in place of the moved nodes.
+    pub fn refile(
+        &mut self,
+        nodes: &[NodeId],
+        target: NodeId,
+        dest: Option<&str>,
+    ) -> Result<NodeId> {
+        if nodes.iter().any(|n| self.tree.node(*n).parent().is_none()) {
+            anyhow::bail!("can't refile the root of the tree");
+        }
+        let new_cursor = self
+            .cursor_after_removing(nodes)
+            .context("nothing to refile")?;
+
+        if let Some(dest) = dest {
+            if nodes.iter().any(|n| self.tree.mirrored_outside(*n)) {
+                anyhow::bail!("can't move nodes that are mirrored elsewhere to another tree");
+            }
+            let (dest_tree, mut dest_storage) = self.open_other_storage(dest)?;
+            let mut dest_tree = dest_tree.unwrap_or_default();
+            if !dest_tree.nodes.contains_key(&target) {
+                anyhow::bail!("{dest} changed while choosing where to refile to");
+            }
+            let target = dest_tree.source(target);
+            self.stop_clock_below(nodes);
+            for n in nodes {
+                dest_tree.import_subtree(&self.tree, *n, target, true);
+            }
+            if let Some(m) = dest_storage
+                .sync(&mut dest_tree)
+                .context("sync refile destination")?
+            {
+                self.message = Some(m);
+            }
+            self.remove_moved_nodes(nodes);
+        } else {
+            if nodes
+                .iter()
+                .any(|n| self.tree.would_create_cycle(*n, target))
+            {
+                anyhow::bail!("can't refile a node inside itself");
+            }
+            for n in nodes {
+                self.tree.reparent_node(*n, self.tree.source(target), None);
+                self.tree.node_mut(*n).touch();
+            }
+        }
+
+        self.drop_detached_roots();
         self.tree_modified = true;
+        Ok(new_cursor)
     }
 
-    pub fn copy_str(&mut self, s: String) {
-        self.snip_stack_strs.push(s);
+    /// Ask the user to choose where to refile `nodes` to, in this tree or the tree stored at
+    /// `dest`.
+    pub fn begin_refile(&mut self, nodes: Vec<NodeId>, dest: Option<String>) -> Result<()> {
+        if nodes.iter().any(|n| self.tree.node(*n).parent().is_none()) {
+            anyhow::bail!("can't refile the root of the tree");
+        }
+        let items = match dest.as_deref() {
+            Some(dest) => {
+                let (t, _) = self.open_other_storage(dest)?;
+                let t = t.unwrap_or_default();
+                node_paths(&t, t.root_id(), |_| true)
+            }
+            None => node_paths(&self.tree, self.tree.root_id(), |t| {
+                !nodes.iter().any(|n| self.tree.would_create_cycle(*n, t))
+            }),
+        };
+        self.pick_request = Some(PickRequest {
+            action: PickAction::Refile { nodes, dest },
+            items,
+        });
+        Ok(())
+    }
+
+    pub fn copy_str(&mut self, s: String, reg: Option<char>) {
+        self.snip_stack_strs.store(reg, s);
+    }
+
+    /// The text in register `reg`, removed from it if `consume` is set.
+    pub fn snip_str(&mut self, reg: Option<char>, consume: bool) -> Option<String> {
+        if consume {
+            self.snip_stack_strs.take(reg)
+        } else {
+            self.snip_stack_strs.get(reg).cloned()
+        }
     }
 
-    pub fn pop_snip_str(&mut self) -> Option<String> {
-        self.snip_stack_strs.pop()
+    /// List what is on the snip stacks and in the registers.
+    fn describe_snips(&self) -> String {
+        fn first_line(s: &str) -> String {
+            let line = s.lines().next().unwrap_or("");
+            let mut r: String = line.chars().take(40).collect();
+            if r.len() < s.len() {
+                r += "…";
+            }
+            r
+        }
+        let nodes = self.snip_stack_nodes.describe(|s| {
+            let text = s
+                .nodes
+                .first()
+                .map(|n| first_line(&self.tree.content(*n).text))
+                .unwrap_or_default();
+            match s.nodes.len() {
+                1 => text,
+                n => format!("{text} (+{} more)", n - 1),
+            }
+        });
+        let strs = self
+            .snip_stack_strs
+            .describe(|s| format!("{:?}", first_line(s)));
+        let mut lines = Vec::new();
+        for (title, snips) in [("nodes", nodes), ("text", strs)] {
+            if !snips.is_empty() {
+                lines.push(format!("{title}:"));
+                lines.extend(snips.into_iter().map(|s| format!("  {s}")));
+            }
+        }
+        if lines.is_empty() {
+            "nothing snipped".into()
+        } else {
+            lines.join("\n")
+        }
     }
 
     /// move the node from being a child of its parent to a sibling of its parent
@@ -163,20 +1115,136 @@ impl Presenter {
             if let Some(grandparent) = self.tree.node(parent).parent() {
                 self.tree
                     .reparent_node(node, grandparent, Some((parent, true)));
+                self.tree.node_mut(node).touch();
             }
         }
         self.tree_modified = true;
     }
 
+    /// Split `node` in two, keeping `before` as its text and putting `after` in a new sibling
+    /// that is returned.
+    pub fn split_node(&mut self, node: NodeId, before: String, after: String) -> Result<NodeId> {
+        let parent = self
+            .tree
+            .node(node)
+            .parent()
+            .context("can't split the root of the tree")?;
+        self.update_node_text(node, before);
+        Ok(self.tree.insert_node(after, parent, node, true))
+    }
+
+    /// Join `node` with its next sibling, adding the sibling's text to the end of its text and
+    /// its children to the end of its children.
+    pub fn join_with_next(&mut self, node: NodeId) -> Result<()> {
+        let next = self.tree.next_child(node).context("no next node to join")?;
+        if self.tree.is_mirror(next) {
+            anyhow::bail!("can't join a mirror into another node");
+        }
+        if self.tree.mirrored_outside(next) {
+            anyhow::bail!("can't join a node that is mirrored elsewhere");
+        }
+        let node = self.tree.source(node);
+        if self.tree.would_create_cycle(next, node) {
+            anyhow::bail!("can't join a node into itself");
+        }
+        if self.tree.redirect_would_create_dependency_cycle(next, node) {
+            anyhow::bail!("joining would make nodes wait on each other");
+        }
+        for c in self.tree.node(next).children.clone() {
+            self.tree.reparent_node(c, node, None);
+        }
+        let next_node = self.tree.node(next);
+        let next_text = next_node.text.clone();
+        let next_properties = next_node.properties.clone();
+        let next_clock = next_node.clock.clone();
+        if self.clocked_in == Some(next_node.uid) {
+            self.clocked_in = Some(self.tree.node(node).uid);
+        }
+        let node = self.tree.node_mut(node);
+        if !next_text.is_empty() {
+            if !node.text.is_empty() {
+                node.text.push(' ');
+            }
+            node.text.push_str(&next_text);
+        }
+        for (k, v) in next_properties {
+            node.properties.entry(k).or_insert(v);
+        }
+        node.clock.extend(next_clock);
+        node.clock.sort_by_key(|e| e.start);
+        node.touch();
+        let node = node.id;
+        self.tree.redirect_dependencies(next, node);
+        self.tree.remove_subtree(next);
+        self.tree_modified = true;
+        Ok(())
+    }
+
+    /// Move `nodes` into a new node put where the first of them was. Returns the new parent.
+    pub fn wrap_nodes(&mut self, nodes: &[NodeId]) -> Result<NodeId> {
+        let first = *nodes.first().context("nothing to wrap")?;
+        let parent = self
+            .tree
+            .node(first)
+            .parent()
+            .context("can't wrap the root of the tree")?;
+        if nodes.iter().any(|n| self.tree.node(*n).parent().is_none()) {
+            anyhow::bail!("can't wrap the root of the tree");
+        }
+        let wrapper = self.tree.insert_node(String::new(), parent, first, false);
+        for n in nodes {
+            self.tree.reparent_node(*n, wrapper, None);
+        }
+        self.tree_modified = true;
+        Ok(wrapper)
+    }
+
+    /// Promote the children of `node` to be its siblings, following it in order.
+    pub fn flatten_node(&mut self, node: NodeId) -> Result<()> {
+        if self.tree.is_mirror(node) {
+            anyhow::bail!("can't flatten a mirror");
+        }
+        let parent = self
+            .tree
+            .node(node)
+            .parent()
+            .context("can't flatten the root of the tree")?;
+        let mut after = node;
+        for c in self.tree.node(node).children.clone() {
+            self.tree.reparent_node(c, parent, Some((after, false)));
+            after = c;
+        }
+        self.tree.node_mut(node).touch();
+        self.tree_modified = true;
+        Ok(())
+    }
+
     pub fn manual_sync(&mut self) -> Result<()> {
         if let Some(s) = self.storage.as_mut() {
-            s.sync(&mut self.tree)?;
+            if let Some(m) = s.sync(&mut self.tree)? {
+                self.message = Some(m);
+            }
             self.tree_modified = false;
         }
         Ok(())
     }
 
-    pub fn process_command(&mut self, cmd: String) -> Result<()> {
+    /// Add anything captured from outside the editor to the tree, saving it right away so that the
+    /// captures aren't lost.
+    pub fn sync_inbox(&mut self) -> Result<()> {
+        if self.storage.as_ref().is_some_and(|s| s.has_captures()) {
+            self.manual_sync()?;
+        }
+        Ok(())
+    }
+
+    /// Run a command from command mode. Returns the node the cursor should move to, if the
+    /// command moved it.
+    pub fn process_command(&mut self, cur_node: NodeId, cmd: String) -> Result<Option<NodeId>> {
+        if let Some(prompt) = self.prompt.take() {
+            return self.answer_prompt(prompt, cmd);
+        }
+
         let mut parts = cmd.split(' ');
         match parts.next() {
             Some("e") => {
@@ -185,36 +1253,306 @@ impl Presenter {
                     parts.next().ok_or_else(|| anyhow::anyhow!("missing URL"))?,
                 )?;
                 self.tree = tree.unwrap_or_default();
+                *self.visible.get_mut() = None;
+                *self.rollups.get_mut() = None;
                 self.storage = Some(storage);
                 self.tree_modified = false;
-                Ok(())
+                self.root_stack.clear();
+                self.clocked_in = self.tree.running_clock().map(|n| self.tree.node(n).uid);
+                self.sync_inbox()?;
+                Ok(Some(self.tree.root_id()))
             }
             Some("s") => {
                 if let Some(new_path) = parts.next() {
                     let (_, storage) = storage::open_storage(new_path)?;
                     self.storage = Some(storage);
                 }
-
-                if let Some(s) = self.storage.as_mut() {
-                    s.sync(&mut self.tree)?;
-                    self.tree_modified = false;
-                }
-                Ok(())
+                self.manual_sync()?;
+                Ok(None)
             }
             Some("q") => {
                 self.should_exit = true;
-                Ok(())
+                Ok(None)
+            }
+            Some("times") => {
+                self.config.show_timestamps = !self.config.show_timestamps;
+                Ok(None)
+            }
+            Some("set") => {
+                let name = parts.next().context("missing setting name")?;
+                self.config
+                    .set(name, parts.next().context("missing value")?)?;
+                *self.rollups.get_mut() = None;
+                Ok(None)
+            }
+            Some("filter") => {
+                self.filter = match parts.next() {
+                    Some("created") => Some(TimeFilter::CreatedWithin(parse_duration(
+                        parts.next().context("missing duration")?,
+                    )?)),
+                    Some("modified") => Some(TimeFilter::ModifiedWithin(parse_duration(
+                        parts.next().context("missing duration")?,
+                    )?)),
+                    Some(f) => anyhow::bail!("unknown filter: {f}"),
+                    None => None,
+                };
+                *self.visible.get_mut() = None;
+                Ok(Some(self.nearest_visible(cur_node)))
+            }
+            Some("sort") => {
+                let mut key = SortKey::Text;
+                let mut descending = false;
+                let mut recursive = false;
+                for p in parts {
+                    match p {
+                        "desc" => descending = true,
+                        "rec" => recursive = true,
+                        k => key = k.parse()?,
+                    }
+                }
+                self.sort_children(cur_node, &key, descending, recursive);
+                Ok(None)
+            }
+            Some("prop") => {
+                let key = parts.next().context("missing property name")?;
+                let value = parts.collect::<Vec<_>>().join(" ");
+                self.set_property(cur_node, key, (!value.is_empty()).then_some(value));
+                Ok(None)
+            }
+            Some("archive") => {
+                let mut this = false;
+                let mut dest = None;
+                for p in parts {
+                    match p {
+                        "this" => this = true,
+                        url => dest = Some(url),
+                    }
+                }
+                let nodes = if this {
+                    vec![cur_node]
+                } else {
+                    self.completed_subtrees()
+                };
+                // move the cursor out of anything that gets archived
+                let new_cursor = self
+                    .tree
+                    .ancestors(cur_node)
+                    .find(|n| nodes.contains(n))
+                    .and_then(|n| self.tree.node(n).parent());
+                self.archive(&nodes, dest)?;
+                Ok(new_cursor)
+            }
+            Some("template") => {
+                let name = parts.next().context("missing template name")?;
+                let vars = parts
+                    .map(|v| v.split_once('=').context("expected variable=value"))
+                    .collect::<Result<Vec<_>>>()?;
+                self.instantiate_template(cur_node, name, &vars).map(Some)
+            }
+            Some("blockedby") => {
+                let query = parts.collect::<Vec<_>>().join(" ");
+                if query.is_empty() {
+                    anyhow::bail!("missing node to wait on");
+                }
+                let blocker = self.find_node(&query)?;
+                self.add_blocker(cur_node, blocker)?;
+                Ok(None)
+            }
+            Some("unblock") => {
+                self.clear_blockers(cur_node);
+                Ok(None)
+            }
+            Some("actionable") => {
+                let leaves = self.tree.actionable_leaves(self.current_root());
+                self.message = Some(if leaves.is_empty() {
+                    "nothing to do".into()
+                } else {
+                    leaves
+                        .iter()
+                        .map(|n| self.tree.path_text(*n))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                });
+                Ok(None)
+            }
+            Some("snips") => {
+                self.message = Some(self.describe_snips());
+                Ok(None)
+            }
+            Some("macros") => {
+                // written so that they can be pasted into `macros` in the config file
+                self.message = Some(if self.config.macros.is_empty() {
+                    "no macros".into()
+                } else {
+                    self.config
+                        .macros
+                        .iter()
+                        .map(|(reg, keys)| format!("{reg:?}: {keys:?},"))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                });
+                Ok(None)
+            }
+            Some("today") => Ok(Some(self.journal(chrono::Local::now().date_naive()))),
+            Some("journal") => {
+                let today = chrono::Local::now().date_naive();
+                let date = match parts.next() {
+                    None | Some("today") => today,
+                    Some("yesterday") => today.pred_opt().context("invalid date")?,
+                    Some("tomorrow") => today.succ_opt().context("invalid date")?,
+                    Some(d) => chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                        .with_context(|| format!("invalid date: {d}"))?,
+                };
+                Ok(Some(self.journal(date)))
+            }
+            Some("refile") => {
+                self.begin_refile(vec![cur_node], parts.next().map(str::to_owned))?;
+                Ok(None)
+            }
+            Some("id") => {
+                self.copy_str(self.tree.node(cur_node).uid.to_string(), None);
+                Ok(None)
             }
             Some("export.md") => {
                 let mut f = std::fs::File::create(parts.next().context("missing export path")?)
                     .context("open file for export")?;
                 self.tree
-                    .write_markdown(&mut f)
+                    .write_markdown(&mut f, self.current_root(), self.config.markdown_progress)
                     .context("export tree to file")?;
-                Ok(())
+                Ok(None)
+            }
+            Some("export.clock") => {
+                let mut f = std::fs::File::create(parts.next().context("missing export path")?)
+                    .context("open file for export")?;
+                self.tree
+                    .write_clock_report(&mut f, self.current_root())
+                    .context("export clock report to file")?;
+                Ok(None)
             }
             Some(cmd) => Err(anyhow::anyhow!("unknown command: {cmd}")),
             None => Err(anyhow::anyhow!("empty command")),
         }
     }
 }
+
+/// List `from` and the nodes below it that satisfy `pred`, in order, along with their path from
+/// the root. Mirrors are left out so that each node appears once.
+pub fn node_paths(
+    tree: &Tree,
+    from: NodeId,
+    pred: impl Fn(NodeId) -> bool,
+) -> Vec<(NodeId, String)> {
+    let mut paths = Vec::new();
+    let mut stack = vec![from];
+    while let Some(n) = stack.pop() {
+        let node = tree.node(n);
+        if node.mirror_of.is_some() {
+            continue;
+        }
+        if pred(n) {
+            let path = if n == tree.root_id() {
+                "(top)".into()
+            } else {
+                tree.path_text(n)
+            };
+            paths.push((n, path));
+        }
+        stack.extend(node.children.iter().rev());
+    }
+    paths
+}
+
+/// Parse a duration like `30m`, `12h`, `3d` or `2w`.
+fn parse_duration(s: &str) -> Result<chrono::Duration> {
+    let unit_start = s
+        .find(|c: char| !c.is_ascii_digit())
+        .context("duration is missing a unit")?;
+    let n: i64 = s[..unit_start].parse().context("parse duration")?;
+    match &s[unit_start..] {
+        "m" => Ok(chrono::Duration::minutes(n)),
+        "h" => Ok(chrono::Duration::hours(n)),
+        "d" => Ok(chrono::Duration::days(n)),
+        "w" => Ok(chrono::Duration::weeks(n)),
+        u => Err(anyhow::anyhow!("unknown duration unit: {u}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    /// A path for a stored tree in a new temporary directory.
+    fn temp_tree_path() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ttt-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("tree.ron")
+    }
+
+    #[test]
+    fn refile_narrowed_root_to_other_tree() {
+        let mut tree = Tree::default();
+        let a = tree.add_node("a".into(), tree.root_id());
+        let b = tree.add_node("b".into(), a);
+        let mut p = Presenter::with_tree(tree, None, Config::default());
+        p.narrow(a);
+        p.narrow(b);
+        let dest = temp_tree_path();
+        let url = format!("file://{}", dest.display());
+        let dest_root = Tree::default().root_id();
+        p.refile(&[a], dest_root, Some(&url)).unwrap();
+        assert_eq!(p.current_root(), p.model().root_id());
+        assert!(p.model().node(p.model().root_id()).children.is_empty());
+        let moved = LocalStorage::new(dest.clone()).load().unwrap().unwrap();
+        assert_eq!(moved.node(moved.root_id()).children.len(), 1);
+        std::fs::remove_dir_all(dest.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn clock_follows_joined_and_moved_nodes() {
+        let mut tree = Tree::default();
+        let a = tree.add_node("a".into(), tree.root_id());
+        let b = tree.add_node("b".into(), tree.root_id());
+        tree.add_node("c".into(), tree.root_id());
+        let mut p = Presenter::with_tree(tree, None, Config::default());
+        p.toggle_clock(b);
+        p.join_with_next(a).unwrap();
+        assert_eq!(p.clocked_in(), Some(a));
+        assert_eq!(p.model().node(a).clock.len(), 1);
+
+        let dest = temp_tree_path();
+        let url = format!("file://{}", dest.display());
+        p.refile(&[a], Tree::default().root_id(), Some(&url))
+            .unwrap();
+        assert_eq!(p.clocked_in(), None);
+        let moved = LocalStorage::new(dest.clone()).load().unwrap().unwrap();
+        assert!(moved.running_clock().is_none());
+        std::fs::remove_dir_all(dest.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn archive_to_other_tree() {
+        let path = temp_tree_path();
+        let mut tree = Tree::default();
+        let a = tree.add_node("a".into(), tree.root_id());
+        let storage: Box<dyn Storage> = Box::new(LocalStorage::new(path.clone()));
+        let mut p = Presenter::with_tree(tree, Some(storage), Config::default());
+        p.narrow(a);
+
+        let open = format!("file://{}", path.display());
+        assert!(p.archive(&[a], Some(&open)).is_err());
+        assert!(p.model().node(a).properties.is_empty());
+
+        let dest = path.with_file_name("archive.ron");
+        p.archive(&[a], Some(&format!("file://{}", dest.display())))
+            .unwrap();
+        assert_eq!(p.current_root(), p.model().root_id());
+        let archive = LocalStorage::new(dest).load().unwrap().unwrap();
+        let archived = archive.node(archive.root_id()).children[0];
+        assert!(archive
+            .node(archived)
+            .properties
+            .contains_key(ARCHIVED_FROM_PROPERTY));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
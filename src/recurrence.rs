@@ -0,0 +1,169 @@
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+
+/// A rule for when a recurring task comes around again, like `daily`, `weekly mon,thu`,
+/// `every 2 weeks` or `monthly on the 1st`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Recurrence {
+    /// Every `n` days.
+    Days(u32),
+    /// Every `every` weeks, on each of `on`, or on the same weekday if `on` is empty.
+    Weeks { every: u32, on: Vec<Weekday> },
+    /// Every `every` months, on day `day` of the month, or the same day if not given.
+    Months { every: u32, day: Option<u32> },
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    s.get(0..3)?.parse().ok()
+}
+
+fn parse_day_of_month(s: &str) -> Option<u32> {
+    s.trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .parse()
+        .ok()
+        .filter(|d| (1..=31).contains(d))
+}
+
+impl std::str::FromStr for Recurrence {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        let mut words = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|w| !w.is_empty() && *w != "on" && *w != "the")
+            .peekable();
+
+        let (every, unit) = match words.next() {
+            Some("daily") => (1, "day"),
+            Some("weekly") => (1, "week"),
+            Some("monthly") => (1, "month"),
+            Some("every") => {
+                let every = match words.peek().and_then(|w| w.parse().ok()) {
+                    Some(n) => {
+                        words.next();
+                        n
+                    }
+                    None => 1,
+                };
+                let unit = words.next().unwrap_or_default();
+                (every, unit.trim_end_matches('s'))
+            }
+            _ => anyhow::bail!("invalid repeat rule: {s}"),
+        };
+        if every == 0 {
+            anyhow::bail!("invalid repeat rule: {s}");
+        }
+
+        let rule = match unit {
+            "day" => Recurrence::Days(every),
+            "week" => Recurrence::Weeks {
+                every,
+                on: words
+                    .by_ref()
+                    .map(|w| parse_weekday(w).ok_or_else(|| anyhow::anyhow!("unknown day: {w}")))
+                    .collect::<Result<_, _>>()?,
+            },
+            "month" => Recurrence::Months {
+                every,
+                day: match words.next() {
+                    Some(w) => Some(
+                        parse_day_of_month(w)
+                            .ok_or_else(|| anyhow::anyhow!("invalid day of month: {w}"))?,
+                    ),
+                    None => None,
+                },
+            },
+            _ => anyhow::bail!("invalid repeat rule: {s}"),
+        };
+        if let Some(w) = words.next() {
+            anyhow::bail!("unexpected {w} in repeat rule");
+        }
+        Ok(rule)
+    }
+}
+
+impl Recurrence {
+    /// The first date after `date` that the rule falls on, treating `date` as the start of the
+    /// recurrence.
+    pub fn next_after(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Recurrence::Days(n) => date + Days::new(*n as u64),
+            Recurrence::Weeks { every, on } if on.is_empty() => date + Days::new(7 * *every as u64),
+            Recurrence::Weeks { every, on } => {
+                let week_start = date.week(Weekday::Mon).first_day();
+                (1..=7 * *every as u64)
+                    .map(|i| date + Days::new(i))
+                    .find(|d| {
+                        let weeks = (d.week(Weekday::Mon).first_day() - week_start).num_weeks();
+                        weeks % *every as i64 == 0 && on.contains(&d.weekday())
+                    })
+                    .expect("rule has a day in every period")
+            }
+            Recurrence::Months { every, day } => {
+                let day = day.unwrap_or(date.day());
+                let month_start = date.with_day(1).unwrap();
+                (0..)
+                    .map(|i| month_start + Months::new(i * every))
+                    .map(|m| {
+                        // use the last day of the month if it is too short
+                        (1..=day).rev().find_map(|d| m.with_day(d)).unwrap_or(m)
+                    })
+                    .find(|d| *d > date)
+                    .unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!("daily".parse::<Recurrence>().unwrap(), Recurrence::Days(1));
+        assert_eq!(
+            "weekly mon,thu".parse::<Recurrence>().unwrap(),
+            Recurrence::Weeks {
+                every: 1,
+                on: vec![Weekday::Mon, Weekday::Thu]
+            }
+        );
+        assert_eq!(
+            "every 2 weeks".parse::<Recurrence>().unwrap(),
+            Recurrence::Weeks {
+                every: 2,
+                on: vec![]
+            }
+        );
+        assert_eq!(
+            "monthly on the 1st".parse::<Recurrence>().unwrap(),
+            Recurrence::Months {
+                every: 1,
+                day: Some(1)
+            }
+        );
+        assert!("sometimes".parse::<Recurrence>().is_err());
+        assert!("weekly on someday".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn next_dates() {
+        // 2026-10-19 is a Monday
+        let mon = date(2026, 10, 19);
+        assert_eq!(Recurrence::Days(1).next_after(mon), date(2026, 10, 20));
+        let mon_thu: Recurrence = "weekly mon,thu".parse().unwrap();
+        assert_eq!(mon_thu.next_after(mon), date(2026, 10, 22));
+        assert_eq!(mon_thu.next_after(date(2026, 10, 22)), date(2026, 10, 26));
+        let fortnightly: Recurrence = "every 2 weeks on mon".parse().unwrap();
+        assert_eq!(fortnightly.next_after(mon), date(2026, 11, 2));
+        let first: Recurrence = "monthly 1".parse().unwrap();
+        assert_eq!(first.next_after(mon), date(2026, 11, 1));
+        let last: Recurrence = "monthly 31".parse().unwrap();
+        assert_eq!(last.next_after(date(2026, 10, 31)), date(2026, 11, 30));
+    }
+}
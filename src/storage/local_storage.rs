@@ -1,11 +1,63 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-use crate::model::Tree;
+use crate::model::{Tree, INBOX_PROPERTY};
 
 use super::Storage;
 
+/// Text captured from outside the editor, waiting to be added to the tree.
+#[derive(Serialize, Deserialize)]
+struct Capture {
+    text: String,
+    time: DateTime<Utc>,
+}
+
+/// Exclusive access to the inbox file next to a tree, held as long as the lock file exists.
+struct InboxLock {
+    path: PathBuf,
+}
+
+impl InboxLock {
+    /// Locks older than this are assumed to have been left behind by a process that crashed.
+    const STALE_AFTER: Duration = Duration::from_secs(10);
+
+    fn acquire(path: PathBuf) -> Result<InboxLock> {
+        for _ in 0..100 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(InboxLock { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = std::fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| SystemTime::now().duration_since(t).ok())
+                        .is_some_and(|age| age > Self::STALE_AFTER);
+                    if stale {
+                        let _ = std::fs::remove_file(&path);
+                    } else {
+                        std::thread::sleep(Duration::from_millis(50));
+                    }
+                }
+                Err(e) => return Err(e).context("create inbox lock"),
+            }
+        }
+        anyhow::bail!("timed out waiting for {}", path.display())
+    }
+}
+
+impl Drop for InboxLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
 pub struct LocalStorage {
     path: PathBuf,
 }
@@ -14,6 +66,33 @@ impl LocalStorage {
     pub fn new(path: PathBuf) -> LocalStorage {
         LocalStorage { path }
     }
+
+    /// Path of a file next to the tree, named after it with `ext` added.
+    fn side_path(&self, ext: &str) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(ext);
+        p.into()
+    }
+
+    fn inbox_path(&self) -> PathBuf {
+        self.side_path(".inbox")
+    }
+
+    fn lock_inbox(&self) -> Result<InboxLock> {
+        InboxLock::acquire(self.side_path(".inbox.lock"))
+    }
+
+    fn write_tree(&self, model: &Tree) -> Result<()> {
+        let f = File::create(&self.path)?;
+        ron::ser::to_writer_pretty(
+            f,
+            model,
+            ron::ser::PrettyConfig::default()
+                .indentor("\t".into())
+                .compact_arrays(true),
+        )?;
+        Ok(())
+    }
 }
 
 impl Storage for LocalStorage {
@@ -21,6 +100,17 @@ impl Storage for LocalStorage {
         self.path.to_string_lossy().into()
     }
 
+    fn same_as(&self, other: &dyn Storage) -> bool {
+        let other = PathBuf::from(other.src_name());
+        match (
+            std::fs::canonicalize(&self.path),
+            std::fs::canonicalize(&other),
+        ) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => self.path == other,
+        }
+    }
+
     fn load(&mut self) -> Result<Option<Tree>> {
         let f = match File::open(&self.path) {
             Ok(f) => f,
@@ -28,19 +118,149 @@ impl Storage for LocalStorage {
             Err(e) => return Err(e.into()),
         };
 
-        ron::de::from_reader(f).map(Some).map_err(Into::into)
+        let mut tree: Tree = ron::de::from_reader(f)?;
+        tree.assign_missing_uids();
+        tree.index_uids();
+        Ok(Some(tree))
     }
 
-    fn sync(&mut self, model: &mut Tree) -> Result<()> {
+    fn sync(&mut self, model: &mut Tree) -> Result<Option<String>> {
+        // hold the lock until the tree is written so that captures are neither lost nor added twice
+        let _lock = self.lock_inbox()?;
+        let had_inbox = model.find_with_property(INBOX_PROPERTY).is_some();
+        let mut added = Vec::new();
+        let mut rejected = Vec::new();
+        let captured = match File::open(self.inbox_path()) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    let line = line?;
+                    match ron::from_str::<Capture>(&line) {
+                        Ok(c) => added.push(model.add_to_inbox(c.text, c.time)),
+                        Err(_) => rejected.push(line),
+                    }
+                }
+                true
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+
         // TODO: actually do a sync rather than overwrite
-        let f = File::create(&self.path)?;
-        ron::ser::to_writer_pretty(
-            f,
-            model,
-            ron::ser::PrettyConfig::default()
-                .indentor("\t".into())
-                .compact_arrays(true),
-        )
-        .map_err(Into::into)
+        if let Err(e) = self.write_tree(model) {
+            // the captures are still in the inbox file, so take them back out of the tree to keep
+            // the next sync from adding them a second time
+            let inbox = added.first().and_then(|n| model.node(*n).parent());
+            for n in added {
+                model.remove_subtree(n);
+            }
+            if let Some(inbox) = inbox.filter(|_| !had_inbox) {
+                model.remove_subtree(inbox);
+            }
+            return Err(e);
+        }
+
+        let message = if rejected.is_empty() {
+            None
+        } else {
+            let path = self.side_path(".inbox.rejected");
+            let mut f = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .context("open rejected captures")?;
+            for line in &rejected {
+                writeln!(f, "{line}").context("write rejected captures")?;
+            }
+            Some(format!(
+                "{} captured lines couldn't be read and were moved to {}",
+                rejected.len(),
+                path.display()
+            ))
+        };
+        if captured {
+            std::fs::remove_file(self.inbox_path()).context("clear inbox")?;
+        }
+        Ok(message)
+    }
+
+    fn capture(&mut self, text: &str) -> Result<()> {
+        let _lock = self.lock_inbox()?;
+        let mut f = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.inbox_path())
+            .context("open inbox")?;
+        let line = ron::to_string(&Capture {
+            text: text.to_owned(),
+            time: Utc::now(),
+        })?;
+        writeln!(f, "{line}").context("write to inbox")
+    }
+
+    fn has_captures(&self) -> bool {
+        self.inbox_path().exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Storage for a tree in a new temporary directory.
+    fn temp_storage() -> LocalStorage {
+        let dir = std::env::temp_dir().join(format!("ttt-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        LocalStorage::new(dir.join("tree.ron"))
+    }
+
+    fn inbox_texts(tree: &Tree) -> Vec<&str> {
+        let inbox = tree.find_with_property(INBOX_PROPERTY).unwrap();
+        let children = &tree.node(inbox).children;
+        children
+            .iter()
+            .map(|c| tree.node(*c).text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn captures_added_on_sync() {
+        let mut s = temp_storage();
+        s.capture("first").unwrap();
+        s.capture("second").unwrap();
+        let mut f = OpenOptions::new()
+            .append(true)
+            .open(s.inbox_path())
+            .unwrap();
+        writeln!(f, "not a capture").unwrap();
+        assert!(s.has_captures());
+
+        let mut tree = Tree::default();
+        let message = s.sync(&mut tree).unwrap();
+        assert!(message.is_some_and(|m| m.starts_with("1 ")));
+        assert_eq!(inbox_texts(&tree), vec!["first", "second"]);
+        assert!(!s.has_captures());
+        assert!(!s.side_path(".inbox.lock").exists());
+        let rejected = std::fs::read_to_string(s.side_path(".inbox.rejected")).unwrap();
+        assert_eq!(rejected, "not a capture\n");
+
+        let loaded = s.load().unwrap().unwrap();
+        assert_eq!(inbox_texts(&loaded), vec!["first", "second"]);
+        assert_eq!(s.sync(&mut tree).unwrap(), None);
+        std::fs::remove_dir_all(s.path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn captures_kept_when_write_fails() {
+        let s = temp_storage();
+        // a directory where the tree should be can't be written over
+        let mut s = LocalStorage::new(s.path.parent().unwrap().to_owned());
+        s.capture("first").unwrap();
+        let mut tree = Tree::default();
+        assert!(s.sync(&mut tree).is_err());
+        assert!(tree.find_with_property(INBOX_PROPERTY).is_none());
+        assert_eq!(tree.node(tree.root_id()).children, vec![]);
+        assert!(s.has_captures());
+        std::fs::remove_dir_all(&s.path).unwrap();
+        std::fs::remove_file(s.inbox_path()).unwrap();
     }
 }
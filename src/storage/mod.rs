@@ -3,8 +3,20 @@ use anyhow::Result;
 
 pub trait Storage {
     fn src_name(&self) -> String;
+    /// Check if `other` stores the same tree as this.
+    fn same_as(&self, other: &dyn Storage) -> bool {
+        self.src_name() == other.src_name()
+    }
     fn load(&mut self) -> Result<Option<Tree>>;
-    fn sync(&mut self, model: &mut Tree) -> Result<()>;
+    /// Write `model` to storage, first adding anything that was captured to its inbox. Captures
+    /// that can't be read are set aside rather than failing the sync, and a message saying so is
+    /// returned.
+    fn sync(&mut self, model: &mut Tree) -> Result<Option<String>>;
+    /// Queue `text` to be added to the inbox of the stored tree on its next sync, without
+    /// rewriting the tree, so that it is safe while another instance has the tree open.
+    fn capture(&mut self, text: &str) -> Result<()>;
+    /// Check if there is captured text waiting to be added to the tree.
+    fn has_captures(&self) -> bool;
 }
 
 mod local_storage;
@@ -1,9 +1,6 @@
-use winit::{
-    event::{ElementState, KeyEvent},
-    keyboard::{Key, NamedKey},
-};
+use winit::keyboard::{Key, NamedKey};
 
-use super::tree_mode::TreeMode;
+use super::{picker::PickerMode, tree_mode::TreeMode, KeyPress};
 
 #[derive(Default)]
 pub struct CmdMode {}
@@ -11,18 +8,14 @@ pub struct CmdMode {}
 impl super::Mode for CmdMode {
     fn process_key(
         &mut self,
-        input: &KeyEvent,
-        _mods: &winit::keyboard::ModifiersState,
+        key: &KeyPress,
         view_state: &mut super::ViewState,
     ) -> Option<Box<dyn super::Mode>> {
-        if input.state == ElementState::Released {
-            return None;
-        }
         let (cursor_index, buf) = view_state.cur_cmd.as_mut().unwrap();
-        match &input.logical_key {
+        match &key.logical_key {
             Key::Named(NamedKey::Escape) => {
                 view_state.abort_command_edit();
-                Some(Box::new(TreeMode))
+                Some(Box::<TreeMode>::default())
             }
             Key::Named(NamedKey::Backspace) => {
                 if buf.len_chars() > 0 && *cursor_index > 0 {
@@ -33,7 +26,13 @@ impl super::Mode for CmdMode {
             }
             Key::Named(NamedKey::Enter) => {
                 view_state.process_command();
-                Some(Box::new(TreeMode))
+                if view_state.cur_cmd.is_some() {
+                    None
+                } else if view_state.picker.is_some() {
+                    Some(Box::new(PickerMode))
+                } else {
+                    Some(Box::<TreeMode>::default())
+                }
             }
             Key::Named(NamedKey::Space) => {
                 let (cursor_index, buf) = view_state.cur_cmd.as_mut().unwrap();
@@ -1,7 +1,6 @@
-use winit::event::{ElementState, KeyEvent};
-use winit::keyboard::{Key, ModifiersState, NamedKey};
+use winit::keyboard::{Key, NamedKey};
 
-use super::motion::*;
+use super::{motion::*, KeyPress};
 
 #[derive(Default)]
 pub struct EditMode {
@@ -11,25 +10,39 @@ pub struct EditMode {
 impl super::Mode for EditMode {
     fn process_key(
         &mut self,
-        input: &KeyEvent,
-        mods: &ModifiersState,
+        key: &KeyPress,
         view_state: &mut super::ViewState,
     ) -> Option<Box<dyn super::Mode>> {
-        if !mods.is_empty() || input.state == ElementState::Released {
+        if key.mods.control_key() && key.logical_key == Key::Named(NamedKey::Enter) {
+            view_state.split_node();
             return None;
         }
-        match &input.logical_key {
+        if key.mods.control_key() || key.mods.alt_key() {
+            return None;
+        }
+        match &key.logical_key {
             Key::Named(NamedKey::Escape) => {
                 view_state.finish_editing();
-                Some(Box::new(super::tree_mode::TreeMode))
+                Some(Box::<super::tree_mode::TreeMode>::default())
+            }
+            Key::Character(ch)
+                if ch == "q" && self.cmd_buffer.is_empty() && view_state.recording().is_some() =>
+            {
+                view_state.stop_recording();
+                None
             }
             Key::Character(ch) => {
                 self.cmd_buffer.push_str(ch.as_str());
-                match Command::parse(&self.cmd_buffer) {
-                    Ok(cmd) => {
+                let parsed = parse_register(&self.cmd_buffer)
+                    .and_then(|(reg, cmd)| Ok((reg, Command::parse(cmd)?)));
+                match parsed {
+                    Ok((reg, cmd)) => {
                         // println!("cmd {cmd:?}");
                         self.cmd_buffer.clear();
-                        view_state.process_normal_cmd(cmd)
+                        view_state.register = reg;
+                        let next_mode = view_state.process_normal_cmd(cmd);
+                        view_state.register = None;
+                        next_mode
                     }
                     Err(ParseError::Unknown) | Err(ParseError::Invalid) => {
                         view_state.prev_error = Some(anyhow::anyhow!(
@@ -1,58 +1,66 @@
-use winit::{
-    event::{ElementState, KeyEvent},
-    keyboard::{Key, NamedKey},
-};
+use winit::keyboard::{Key, NamedKey};
 
-use super::{edit_mode::EditMode, tree_mode::TreeMode};
+use super::{edit_mode::EditMode, tree_mode::TreeMode, KeyPress};
 
 pub struct InsertMode;
 
 impl super::Mode for InsertMode {
     fn process_key(
         &mut self,
-        input: &KeyEvent,
-        _mods: &winit::keyboard::ModifiersState,
+        key: &KeyPress,
         view_state: &mut super::ViewState,
     ) -> Option<Box<dyn super::Mode>> {
-        if input.state == ElementState::Released {
-            return None;
-        }
         let (cursor_index, buf) = view_state.cur_edit.as_mut().unwrap();
-        match &input.logical_key {
-            Key::Named(NamedKey::Tab) => Some(Box::<EditMode>::default()),
+        match &key.logical_key {
+            Key::Named(NamedKey::Tab) => {
+                view_state.finish_change();
+                Some(Box::<EditMode>::default())
+            }
             Key::Named(NamedKey::Escape) => {
+                view_state.finish_change();
                 view_state.finish_editing();
-                Some(Box::new(TreeMode))
+                Some(Box::<TreeMode>::default())
             }
             Key::Named(NamedKey::Backspace) => {
                 if buf.len_chars() > 0 && *cursor_index > 0 {
                     buf.remove((*cursor_index - 1)..*cursor_index);
                     *cursor_index -= 1;
+                    view_state.record_backspace();
                 }
                 None
             }
+            Key::Named(NamedKey::Enter) if key.mods.control_key() => {
+                view_state.abandon_change();
+                view_state.split_node();
+                None
+            }
             Key::Named(NamedKey::Enter) => {
                 buf.insert_char(*cursor_index, '\n');
                 *cursor_index += 1;
+                view_state.record_inserted("\n");
                 None
             }
             Key::Named(NamedKey::Space) => {
                 buf.insert_char(*cursor_index, ' ');
                 *cursor_index += 1;
+                view_state.record_inserted(" ");
                 None
             }
             Key::Named(NamedKey::ArrowLeft) => {
                 *cursor_index = cursor_index.saturating_sub(1);
+                view_state.abandon_change();
                 None
             }
             Key::Named(NamedKey::ArrowRight) => {
                 *cursor_index += 1;
+                view_state.abandon_change();
                 None
             }
             Key::Character(c) => {
                 let (cursor_index, buf) = view_state.cur_edit.as_mut().unwrap();
                 buf.insert(*cursor_index, c.as_str());
                 *cursor_index += 1;
+                view_state.record_inserted(c.as_str());
                 None
             }
             _ => None,
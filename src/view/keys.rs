@@ -0,0 +1,213 @@
+use anyhow::Result;
+use winit::{
+    event::KeyEvent,
+    keyboard::{Key, KeyCode, ModifiersState, NamedKey, NativeKeyCode, PhysicalKey, SmolStr},
+};
+
+/// A key being pressed, as the modes see it. Unlike winit's `KeyEvent` these can be made up, so
+/// that macros can record them and play them back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyPress {
+    pub logical_key: Key,
+    pub physical_key: PhysicalKey,
+    pub mods: ModifiersState,
+}
+
+/// Names of the keys that can be written in angle brackets, like `<esc>`.
+const NAMED_KEYS: &[(&str, NamedKey, KeyCode)] = &[
+    ("esc", NamedKey::Escape, KeyCode::Escape),
+    ("cr", NamedKey::Enter, KeyCode::Enter),
+    ("tab", NamedKey::Tab, KeyCode::Tab),
+    ("bs", NamedKey::Backspace, KeyCode::Backspace),
+    ("space", NamedKey::Space, KeyCode::Space),
+    ("left", NamedKey::ArrowLeft, KeyCode::ArrowLeft),
+    ("right", NamedKey::ArrowRight, KeyCode::ArrowRight),
+    ("up", NamedKey::ArrowUp, KeyCode::ArrowUp),
+    ("down", NamedKey::ArrowDown, KeyCode::ArrowDown),
+];
+
+/// Where a character is on a US keyboard, for the modes that look at physical keys.
+fn key_code(c: char) -> PhysicalKey {
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::KeyA,
+        KeyCode::KeyB,
+        KeyCode::KeyC,
+        KeyCode::KeyD,
+        KeyCode::KeyE,
+        KeyCode::KeyF,
+        KeyCode::KeyG,
+        KeyCode::KeyH,
+        KeyCode::KeyI,
+        KeyCode::KeyJ,
+        KeyCode::KeyK,
+        KeyCode::KeyL,
+        KeyCode::KeyM,
+        KeyCode::KeyN,
+        KeyCode::KeyO,
+        KeyCode::KeyP,
+        KeyCode::KeyQ,
+        KeyCode::KeyR,
+        KeyCode::KeyS,
+        KeyCode::KeyT,
+        KeyCode::KeyU,
+        KeyCode::KeyV,
+        KeyCode::KeyW,
+        KeyCode::KeyX,
+        KeyCode::KeyY,
+        KeyCode::KeyZ,
+    ];
+    const DIGITS: [KeyCode; 10] = [
+        KeyCode::Digit0,
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    PhysicalKey::Code(match c {
+        'a'..='z' => LETTERS[c as usize - 'a' as usize],
+        'A'..='Z' => LETTERS[c as usize - 'A' as usize],
+        '0'..='9' => DIGITS[c as usize - '0' as usize],
+        '-' | '_' => KeyCode::Minus,
+        ';' | ':' => KeyCode::Semicolon,
+        '\'' | '"' => KeyCode::Quote,
+        '[' | '{' => KeyCode::BracketLeft,
+        ']' | '}' => KeyCode::BracketRight,
+        ',' | '<' => KeyCode::Comma,
+        '.' | '>' => KeyCode::Period,
+        '/' | '?' => KeyCode::Slash,
+        ' ' => KeyCode::Space,
+        _ => return PhysicalKey::Unidentified(NativeKeyCode::Unidentified),
+    })
+}
+
+impl KeyPress {
+    pub fn new(event: &KeyEvent, mods: ModifiersState) -> KeyPress {
+        KeyPress {
+            logical_key: event.logical_key.clone(),
+            physical_key: event.physical_key,
+            mods,
+        }
+    }
+
+    fn from_char(c: char, mut mods: ModifiersState) -> KeyPress {
+        if c.is_uppercase() {
+            mods |= ModifiersState::SHIFT;
+        }
+        KeyPress {
+            logical_key: Key::Character(SmolStr::new(c.to_string())),
+            physical_key: key_code(c),
+            mods,
+        }
+    }
+
+    fn from_named(name: &str, mods: ModifiersState) -> Option<KeyPress> {
+        if name == "lt" {
+            return Some(KeyPress::from_char('<', mods));
+        }
+        if name.chars().count() == 1 {
+            return Some(KeyPress::from_char(name.chars().next().unwrap(), mods));
+        }
+        NAMED_KEYS
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, key, code)| KeyPress {
+                logical_key: Key::Named(*key),
+                physical_key: PhysicalKey::Code(*code),
+                mods,
+            })
+    }
+
+    /// Write the key the way `parse_keys` reads it, or None if it can't be written down.
+    fn notation(&self) -> Option<String> {
+        let mut prefix = String::new();
+        if self.mods.control_key() {
+            prefix.push_str("c-");
+        }
+        if self.mods.alt_key() {
+            prefix.push_str("a-");
+        }
+        let name = match &self.logical_key {
+            Key::Character(c) if prefix.is_empty() && c.as_str() != "<" => {
+                return Some(c.to_string())
+            }
+            Key::Character(c) if c.as_str() == "<" => "lt",
+            Key::Character(c) => c.as_str(),
+            Key::Named(named) => {
+                if self.mods.shift_key() {
+                    prefix.push_str("s-");
+                }
+                NAMED_KEYS.iter().find(|(_, k, _)| k == named)?.0
+            }
+            _ => return None,
+        };
+        Some(format!("<{prefix}{name}>"))
+    }
+}
+
+/// Read keys written like `dd}p`, with special keys and modifiers in angle brackets like `<esc>`,
+/// `<cr>` or `<c-j>`, and `<lt>` for `<` itself.
+pub fn parse_keys(s: &str) -> Result<Vec<KeyPress>> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c != '<' {
+            keys.push(KeyPress::from_char(c, ModifiersState::empty()));
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let end = rest
+            .find('>')
+            .ok_or_else(|| anyhow::anyhow!("missing > in {rest}"))?;
+        let mut name = &rest[1..end];
+        let mut mods = ModifiersState::empty();
+        loop {
+            if let Some(n) = name.strip_prefix("c-") {
+                mods |= ModifiersState::CONTROL;
+                name = n;
+            } else if let Some(n) = name.strip_prefix("a-") {
+                mods |= ModifiersState::ALT;
+                name = n;
+            } else if let Some(n) = name.strip_prefix("s-") {
+                mods |= ModifiersState::SHIFT;
+                name = n;
+            } else {
+                break;
+            }
+        }
+        keys.push(
+            KeyPress::from_named(name, mods)
+                .ok_or_else(|| anyhow::anyhow!("unknown key: <{name}>"))?,
+        );
+        rest = &rest[end + 1..];
+    }
+    Ok(keys)
+}
+
+/// Write `keys` the way `parse_keys` reads them, leaving out any that can't be written down.
+pub fn format_keys(keys: &[KeyPress]) -> String {
+    keys.iter().filter_map(KeyPress::notation).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notation() {
+        let keys = parse_keys("dd}p<c-j>Ohi<space><lt><esc>").unwrap();
+        assert_eq!(keys.len(), 11);
+        assert_eq!(keys[0].logical_key, Key::Character("d".into()));
+        assert_eq!(keys[0].physical_key, PhysicalKey::Code(KeyCode::KeyD));
+        assert_eq!(keys[4].mods, ModifiersState::CONTROL);
+        assert_eq!(keys[5].mods, ModifiersState::SHIFT);
+        assert_eq!(keys[10].logical_key, Key::Named(NamedKey::Escape));
+        assert_eq!(format_keys(&keys), "dd}p<c-j>Ohi<space><lt><esc>");
+        assert!(parse_keys("<nope>").is_err());
+        assert!(parse_keys("<esc").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use std::cell::RefCell;
 
 use crate::{
-    model::{NodeId, Tree},
+    model::{format_duration, NodeId, Tree},
     presenter::Presenter,
 };
 
@@ -23,6 +23,10 @@ use super::*;
 use ropey::Rope;
 
 const PAD: f32 = 6.0;
+/// Number of matches shown in the picker at once.
+const PICKER_ROWS: usize = 12;
+/// Most keys macros can play for one key press, so that a macro calling itself stops.
+const MAX_PLAYED_KEYS: usize = 100_000;
 
 pub struct View {
     font_collection: FontCollection,
@@ -38,6 +42,9 @@ pub struct View {
     root_path_text_style: TextStyle,
     error_style: TextStyle,
     struck_text_style: TextStyle,
+    blocked_text_style: TextStyle,
+    mirror_mark_style: TextStyle,
+    meta_text_style: TextStyle,
 
     focused: bool,
 
@@ -47,6 +54,8 @@ pub struct View {
     mode_just_switched: bool,
     state: ViewState,
     cur_node_rect: RefCell<Option<Rect>>,
+    /// Nodes selected in visual mode, worked out once per frame.
+    selected_nodes: RefCell<Vec<NodeId>>,
     screen_y: RefCell<f32>,
 }
 
@@ -79,6 +88,19 @@ impl View {
         struck_text_style.set_foreground_paint(&fg_paint_fill_dark);
         struck_text_style.set_decoration_type(TextDecoration::LINE_THROUGH);
 
+        let mut blocked_text_style = text_style.clone();
+        blocked_text_style.set_foreground_paint(&fg_paint_fill_dark);
+
+        let mut mirror_mark_style = text_style.clone();
+        mirror_mark_style.set_foreground_paint(&create_paint(
+            Color4f::new(0.3, 0.6, 0.9, 1.0),
+            PaintStyle::Fill,
+        ));
+
+        let mut meta_text_style = TextStyle::new();
+        meta_text_style.set_foreground_paint(&edge_paint);
+        meta_text_style.set_font_size(16.0);
+
         let root_path_font_size = 18.0;
         let mut root_path_sep_style = TextStyle::new();
         root_path_sep_style.set_foreground_paint(&edge_paint);
@@ -99,7 +121,7 @@ impl View {
 
         View {
             state: ViewState::new(presenter),
-            cur_mode: Box::new(tree_mode::TreeMode),
+            cur_mode: Box::<tree_mode::TreeMode>::default(),
             font_collection,
             pg_style,
             cmd_bg_paint,
@@ -111,11 +133,15 @@ impl View {
             focused: false,
             mode_just_switched: false,
             cur_node_rect: RefCell::default(),
+            selected_nodes: RefCell::default(),
             screen_y: RefCell::new(0.0),
             root_path_sep_style,
             root_path_text_style,
             error_style,
             struck_text_style,
+            blocked_text_style,
+            mirror_mark_style,
+            meta_text_style,
         }
     }
 
@@ -163,6 +189,10 @@ impl View {
         }
     }
 
+    /// Draw `node_id` and its children. `in_mirror` is set for the nodes shown below a mirror,
+    /// which are the mirrored node's own children and so can't hold the cursor, be selected or be
+    /// folded separately from where they really are.
+    #[allow(clippy::too_many_arguments)]
     fn draw_node(
         &self,
         canvas: &Canvas,
@@ -171,10 +201,12 @@ impl View {
         (cur_x, cur_y): (f32, f32),
         par_x: f32,
         canvas_size: LogicalSize<f32>,
+        in_mirror: bool,
     ) -> (f32, f32, f32) {
-        let node = model.node(node_id);
+        let node = model.content(node_id);
+        let is_cursor = !in_mirror && node_id == self.state.cur_node;
 
-        let paint = if self.focused && node_id == self.state.cur_node {
+        let paint = if self.focused && is_cursor {
             &self.active_edge_paint
         } else {
             &self.edge_paint
@@ -182,30 +214,100 @@ impl View {
 
         // create Skia paragraph for node text
         let mut pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
-        if node.struckout {
-            pg.push_style(&self.struck_text_style);
+        // the marker is left out while editing so that it doesn't throw off the cursor position
+        let editing = is_cursor && self.state.cur_edit.is_some();
+        if model.is_mirror(node_id) && !editing {
+            pg.push_style(&self.mirror_mark_style);
+            pg.add_text("↪ ");
+            pg.pop();
+        }
+        // dim nodes that are waiting on other nodes to be struck out
+        let text_style = if node.struckout {
+            Some(&self.struck_text_style)
+        } else if model.is_blocked(node_id) {
+            Some(&self.blocked_text_style)
+        } else {
+            None
+        };
+        if let Some(style) = text_style {
+            pg.push_style(style);
         }
         //pg.add_text(format!("{} ", node_id));
-        if node_id == self.state.cur_node && self.state.cur_edit.is_some() {
+        if editing {
             let (_, text) = self.state.cur_edit.as_ref().unwrap();
             add_rope_to_paragraph(&mut pg, text);
         } else {
             pg.add_text(&node.text);
         }
+        if text_style.is_some() {
+            pg.pop();
+        }
+        if !node.properties.is_empty() {
+            pg.push_style(&self.meta_text_style);
+            for (k, v) in node.properties.iter() {
+                pg.add_text(format!("  {k}={v}"));
+            }
+            pg.pop();
+        }
+        let config = self.state.presenter.config();
+        let rollup = self.state.presenter.rollup(node_id);
+        if config.show_progress && !node.children.is_empty() {
+            let (done, total) = rollup.progress;
+            pg.push_style(&self.meta_text_style);
+            if config.progress_percent {
+                pg.add_text(format!("  {}%", done * 100 / total));
+            } else {
+                pg.add_text(format!("  [{done}/{total}]"));
+            }
+            pg.pop();
+        }
+        if let Some((agg, v)) = rollup.value.filter(|_| !node.children.is_empty()) {
+            pg.push_style(&self.meta_text_style);
+            pg.add_text(format!("  {} {}", agg.label(), format_number(v)));
+            pg.pop();
+        }
+        let clocked = rollup.clocked_time();
+        if clocked > chrono::Duration::zero() {
+            pg.push_style(&self.meta_text_style);
+            pg.add_text(format!("  ⏱ {}", format_duration(clocked)));
+            pg.pop();
+        }
+        if config.show_timestamps {
+            pg.push_style(&self.meta_text_style);
+            pg.add_text(format!(
+                "  created {}, modified {}",
+                format_timestamp(node.created),
+                format_timestamp(node.modified)
+            ));
+            pg.pop();
+        }
         let mut pg = pg.build();
         pg.layout(canvas_size.width - cur_x - PAD * 2.0);
 
-        if node_id == self.state.cur_node {
+        if is_cursor {
             let r = Rect::from_xywh(cur_x, cur_y, pg.max_width(), pg.height());
             *self.cur_node_rect.borrow_mut() = Some(r);
             // canvas.draw_rect(r, &self.active_edge_paint);
         }
 
+        // highlight nodes selected in visual mode
+        if !in_mirror && self.selected_nodes.borrow().contains(&node_id) {
+            canvas.draw_rect(
+                Rect::from_xywh(
+                    cur_x - PAD,
+                    cur_y,
+                    pg.max_intrinsic_width() + PAD * 2.0,
+                    pg.height(),
+                ),
+                &self.cmd_bg_paint,
+            );
+        }
+
         // draw the node's text
         pg.paint(canvas, (cur_x, cur_y));
 
         // if we're editing, draw the cursor
-        if node_id == self.state.cur_node && self.state.cur_edit.is_some() {
+        if editing {
             let (cursor_index, buf) = self.state.cur_edit.as_ref().unwrap();
             self.draw_cursor(canvas, &pg, *cursor_index, buf, cur_x, cur_y);
         }
@@ -217,7 +319,7 @@ impl View {
             paint,
         );
 
-        if self.state.folded_nodes.contains(&node_id) {
+        if !in_mirror && self.state.folded_nodes.contains(&node_id) {
             // draw the vertical line from the top of this node to the bottom
             let bottom = (cur_x - PAD, cur_y + pg.height());
             canvas.draw_line((cur_x - PAD, cur_y), bottom, paint);
@@ -232,9 +334,20 @@ impl View {
 
             let mut last_c_h = cur_y + pg.height();
             let mut last_c_m = 0.0;
-            for child in node.children.iter() {
-                let (_, h, m) =
-                    self.draw_node(canvas, model, *child, (cnew_x, cnew_y), cur_x, canvas_size);
+            for child in node
+                .children
+                .iter()
+                .filter(|c| self.state.presenter.node_visible(**c))
+            {
+                let (_, h, m) = self.draw_node(
+                    canvas,
+                    model,
+                    *child,
+                    (cnew_x, cnew_y),
+                    cur_x,
+                    canvas_size,
+                    in_mirror || model.is_mirror(node_id),
+                );
                 last_c_h = cnew_y;
                 last_c_m = m;
                 cnew_y += h + PAD * 2.0;
@@ -268,23 +381,114 @@ impl View {
 
     fn draw_cmdline(&self, canvas: &Canvas, canvas_size: LogicalSize<f32>) {
         if let Some((cursor_index, cmdline)) = self.state.cur_cmd.as_ref() {
+            // if the presenter is asking a question, show it in front of the answer
+            let prompt = self.state.presenter.prompt().map(|p| {
+                let mut pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
+                pg.push_style(&self.root_path_sep_style);
+                pg.add_text(p);
+                let mut pg = pg.build();
+                pg.layout(canvas_size.width - PAD * 4.0);
+                pg
+            });
+            let xpos = PAD * 2.0
+                + prompt
+                    .as_ref()
+                    .map_or(0.0, |p| p.max_intrinsic_width() + PAD);
+
             let mut pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
             add_rope_to_paragraph(&mut pg, cmdline);
             let mut pg = pg.build();
-            pg.layout(canvas_size.width - PAD * 4.0);
+            pg.layout(canvas_size.width - xpos - PAD * 2.0);
             let ypos = canvas_size.height - PAD * 6.0;
             canvas.draw_rect(
                 Rect::from_xywh(0.0, ypos - PAD, canvas_size.width, pg.height() + PAD * 2.0),
                 &self.cmd_bg_paint,
             );
-            pg.paint(canvas, (PAD * 2.0, ypos));
-            self.draw_cursor(canvas, &pg, *cursor_index, cmdline, PAD * 2.0, ypos);
+            if let Some(p) = prompt {
+                p.paint(canvas, (PAD * 2.0, ypos));
+            }
+            pg.paint(canvas, (xpos, ypos));
+            self.draw_cursor(canvas, &pg, *cursor_index, cmdline, xpos, ypos);
+        }
+    }
+
+    fn draw_picker(&self, canvas: &Canvas, canvas_size: LogicalSize<f32>) {
+        let picker = match self.state.picker.as_ref() {
+            Some(p) => p,
+            None => return,
+        };
+        let width = canvas_size.width - PAD * 16.0;
+        let xpos = PAD * 8.0;
+        let mut ypos = canvas_size.height / 6.0;
+
+        let query = Rope::from_str(&picker.query);
+        let mut prompt = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
+        prompt.push_style(&self.root_path_sep_style);
+        prompt.add_text(picker.prompt());
+        let mut prompt = prompt.build();
+        prompt.layout(width);
+        let query_x = xpos + prompt.max_intrinsic_width() + PAD;
+        let mut query_pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
+        add_rope_to_paragraph(&mut query_pg, &query);
+        let mut query_pg = query_pg.build();
+        query_pg.layout(width - query_x);
+
+        // keep the selection in view
+        let first = picker.selected.saturating_sub(PICKER_ROWS - 1);
+        let rows: Vec<Paragraph> = picker
+            .matches()
+            .enumerate()
+            .skip(first)
+            .take(PICKER_ROWS)
+            .map(|(i, label)| {
+                let mut pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
+                if i == picker.selected {
+                    pg.push_style(&self.root_path_text_style);
+                    pg.add_text(format!("▸ {label}"));
+                } else {
+                    pg.push_style(&self.root_path_sep_style);
+                    pg.add_text(format!("  {label}"));
+                }
+                let mut pg = pg.build();
+                pg.layout(width);
+                pg
+            })
+            .collect();
+
+        let height = query_pg.height().max(prompt.height())
+            + rows.iter().map(|r| r.height()).sum::<f32>()
+            + PAD * 2.0;
+        canvas.draw_rect(
+            Rect::from_xywh(
+                xpos - PAD * 2.0,
+                ypos - PAD * 2.0,
+                width + PAD * 4.0,
+                height + PAD * 4.0,
+            ),
+            &self.cmd_bg_paint,
+        );
+
+        prompt.paint(canvas, (xpos, ypos));
+        query_pg.paint(canvas, (query_x, ypos));
+        if picker.typing {
+            self.draw_cursor(canvas, &query_pg, query.len_chars(), &query, query_x, ypos);
+        }
+        ypos += query_pg.height().max(prompt.height()) + PAD * 2.0;
+        for row in rows {
+            row.paint(canvas, (xpos, ypos));
+            ypos += row.height();
         }
     }
 
     pub fn draw(&self, canvas: &Canvas, canvas_size: LogicalSize<f32>) {
         let model = self.state.presenter.model();
 
+        *self.selected_nodes.borrow_mut() = if self.state.visual.is_some() {
+            self.state.selection()
+        } else {
+            Vec::new()
+        };
+
         self.draw_node(
             canvas,
             model,
@@ -292,12 +496,28 @@ impl View {
             (PAD * 8.0, PAD * 8.0 + *self.screen_y.borrow()),
             0.0,
             canvas_size,
+            false,
         );
 
         self.draw_status_line(canvas, (PAD * 2.0, PAD * 4.0), canvas_size);
 
         self.draw_cmdline(canvas, canvas_size);
 
+        self.draw_picker(canvas, canvas_size);
+
+        if let Some(msg) = self.state.presenter.message() {
+            let mut pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
+            pg.add_text(msg);
+            let mut pg = pg.build();
+            pg.layout(canvas_size.width - PAD * 4.0);
+            let ypos = canvas_size.height - PAD * 8.0 - pg.height();
+            canvas.draw_rect(
+                Rect::from_xywh(0.0, ypos - PAD, canvas_size.width, pg.height() + PAD * 2.0),
+                &self.cmd_bg_paint,
+            );
+            pg.paint(canvas, (PAD * 2.0, ypos));
+        }
+
         if let Some(err) = self.state.prev_error.as_ref() {
             let mut pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
             pg.push_style(&self.error_style);
@@ -311,30 +531,51 @@ impl View {
         self.update_scroll(canvas_size);
     }
 
+    fn process_key(&mut self, key: &KeyPress) {
+        if let Some(new_mode) = self.cur_mode.process_key(key, &mut self.state) {
+            self.cur_mode = new_mode;
+            self.mode_just_switched = true;
+        } else if self.mode_just_switched {
+            self.mode_just_switched = false;
+        }
+    }
+
     pub fn process_event(&mut self, e: WindowEvent) -> bool {
         match e {
-            WindowEvent::KeyboardInput { event, .. } => {
-                if self.state.prev_error.is_some() && event.state == ElementState::Pressed {
-                    self.state.prev_error = None;
-                }
-                if let Some(new_mode) =
-                    self.cur_mode
-                        .process_key(&event, &self.mods, &mut self.state)
-                {
-                    self.cur_mode = new_mode;
-                    self.mode_just_switched = true;
-                } else if self.mode_just_switched {
-                    self.mode_just_switched = false;
+            WindowEvent::KeyboardInput { event, .. } if event.state == ElementState::Pressed => {
+                self.state.prev_error = None;
+                self.state.presenter.clear_message();
+                let key = KeyPress::new(&event, self.mods);
+                self.state.record_key(&key);
+                self.process_key(&key);
+
+                // play any keys from macros the key started
+                let mut played = 0;
+                while let Some(key) = self.state.next_played_key() {
+                    if self.state.prev_error.is_some() {
+                        self.state.stop_playback();
+                        break;
+                    }
+                    played += 1;
+                    if played > MAX_PLAYED_KEYS {
+                        self.state.stop_playback();
+                        self.state.prev_error = Some(anyhow::anyhow!("macro played too many keys"));
+                        break;
+                    }
+                    self.process_key(&key);
                 }
             }
             WindowEvent::ModifiersChanged(mods) => self.mods = mods.state(),
             WindowEvent::Focused(focused) => {
                 self.focused = focused;
-                if !focused {
-                    match self.state.presenter.manual_sync() {
-                        Ok(()) => {}
-                        Err(e) => self.state.prev_error = Some(e),
-                    }
+                let res = if focused {
+                    // pick up anything captured while we were away
+                    self.state.presenter.sync_inbox()
+                } else {
+                    self.state.presenter.manual_sync()
+                };
+                if let Err(e) = res {
+                    self.state.prev_error = Some(e);
                 }
             }
             _ => {}
@@ -365,16 +606,19 @@ impl View {
         let tree = self.state.presenter.model();
         let mut strs = Vec::new();
         let mut cur_node = self.state.presenter.current_root();
-        strs.push(trunc_str(&tree.node(cur_node).text));
+        strs.push(trunc_str(&tree.content(cur_node).text));
         while let Some(parent) = tree.node(cur_node).parent() {
             cur_node = parent;
-            strs.push(trunc_str(&tree.node(cur_node).text));
+            strs.push(trunc_str(&tree.content(cur_node).text));
         }
         let mut pg = ParagraphBuilder::new(&self.pg_style, &self.font_collection);
         pg.push_style(&self.root_path_text_style);
 
         pg.push_style(&self.root_path_sep_style);
         pg.add_text(self.cur_mode.name());
+        if let Some(reg) = self.state.recording() {
+            pg.add_text(format!(" recording @{reg}"));
+        }
         pg.add_text("  ");
         pg.pop();
 
@@ -386,6 +630,25 @@ impl View {
             pg.add_text("*");
         }
 
+        if let Some(clocked) = self.state.presenter.clocked_in() {
+            let running = tree
+                .content(clocked)
+                .clock
+                .last()
+                .map(|e| e.duration())
+                .unwrap_or_else(chrono::Duration::zero);
+            pg.push_style(&self.root_path_sep_style);
+            pg.add_text("  ⏱ ");
+            pg.pop();
+            pg.add_text(format!(
+                "{} {}:{:02}:{:02}",
+                trunc_str(&tree.content(clocked).text),
+                running.num_hours(),
+                running.num_minutes() % 60,
+                running.num_seconds() % 60
+            ));
+        }
+
         for s in strs.into_iter().rev() {
             pg.push_style(&self.root_path_sep_style);
             pg.add_text(" > ");
@@ -408,6 +671,22 @@ fn add_rope_to_paragraph(pg: &mut ParagraphBuilder, text: &Rope) {
     }
 }
 
+fn format_number(v: f64) -> String {
+    let s = format!("{v:.2}");
+    s.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+fn format_timestamp(t: Option<chrono::DateTime<chrono::Utc>>) -> String {
+    t.map_or_else(
+        || "unknown".into(),
+        |t| {
+            t.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        },
+    )
+}
+
 fn create_paint(col: Color4f, style: PaintStyle) -> Paint {
     let mut paint = Paint::new(col, None);
     paint.set_anti_alias(true);
@@ -1,12 +1,13 @@
-use winit::event::KeyEvent;
-use winit::keyboard::ModifiersState;
-
 mod cmd_mode;
 mod edit_mode;
 mod insert_mode;
+mod keys;
 mod main_view;
 mod motion;
+mod picker;
 mod tree_mode;
+mod tree_motion;
+mod visual_mode;
 
 mod view_state;
 pub use view_state::ViewState;
@@ -16,13 +17,11 @@ pub enum CursorShape {
     Line,
 }
 
+pub use keys::KeyPress;
+
 pub trait Mode {
-    fn process_key(
-        &mut self,
-        input: &KeyEvent,
-        mods: &ModifiersState,
-        view_state: &mut ViewState,
-    ) -> Option<Box<dyn Mode>>;
+    /// Handle a key being pressed, returning the mode to switch to if it should change.
+    fn process_key(&mut self, key: &KeyPress, view_state: &mut ViewState) -> Option<Box<dyn Mode>>;
 
     fn name(&self) -> &'static str;
 
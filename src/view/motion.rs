@@ -15,6 +15,20 @@ pub enum ParseError {
     Unknown,
 }
 
+/// Split a register like `"a` or `"2` off the start of a command, returning it and the rest of
+/// the command.
+pub fn parse_register(cmd: &str) -> Result<(Option<char>, &str), ParseError> {
+    let rest = match cmd.strip_prefix('"') {
+        Some(r) => r,
+        None => return Ok((None, cmd)),
+    };
+    let reg = rest.chars().next().ok_or(ParseError::Incomplete)?;
+    if !reg.is_ascii_lowercase() && !('1'..='9').contains(&reg) {
+        return Err(ParseError::Invalid);
+    }
+    Ok((Some(reg), &rest[1..]))
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum CharClass {
     Whitespace,
@@ -688,19 +702,51 @@ impl Motion {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Command {
     Move(Motion),
-    Insert { at: Option<Motion>, new_line: bool },
+    Insert {
+        at: Option<Motion>,
+        new_line: bool,
+    },
     ReplaceChar(char),
     Change(Motion),
     Delete(Motion),
     Copy(Motion),
-    Put { consume: bool },
+    Put {
+        consume: bool,
+    },
+    /// Repeat the last change, with a new count if one is given.
+    Repeat(Option<usize>),
 }
 
 impl Command {
+    /// Whether the command changes the text, so that `.` can repeat it.
+    pub fn is_change(&self) -> bool {
+        !matches!(
+            self,
+            Command::Move(_) | Command::Copy(_) | Command::Repeat(_)
+        )
+    }
+
+    /// The same command with its motion's count replaced by `count`, or None if it doesn't have
+    /// one.
+    pub fn with_count(self, count: usize) -> Option<Command> {
+        let with = |m: Motion| Motion { count, ..m };
+        match self {
+            Command::Change(m) => Some(Command::Change(with(m))),
+            Command::Delete(m) => Some(Command::Delete(with(m))),
+            _ => None,
+        }
+    }
+
     pub fn parse(cmd: &str) -> Result<Command, ParseError> {
+        match cmd.trim_start_matches(|c: char| c.is_ascii_digit()) {
+            "." => return Ok(Command::Repeat(cmd[..cmd.len() - 1].parse().ok())),
+            // a count on its own, waiting for what it applies to
+            "" if !cmd.is_empty() => return Err(ParseError::Incomplete),
+            _ => {}
+        }
         match cmd.chars().next() {
             Some('i') => Ok(Command::Insert {
                 at: None,
@@ -769,6 +815,30 @@ mod tests {
         Rope::from_str("word\nw0rd w##d ++++ word\n")
     }
 
+    #[test]
+    fn parse_repeat() {
+        assert!(matches!(Command::parse("."), Ok(Command::Repeat(None))));
+        assert!(matches!(
+            Command::parse("12."),
+            Ok(Command::Repeat(Some(12)))
+        ));
+        assert!(matches!(Command::parse("3"), Err(ParseError::Incomplete)));
+        assert!(matches!(
+            Command::parse("3w"),
+            Ok(Command::Move(Motion { count: 3, .. }))
+        ));
+    }
+
+    #[test]
+    fn parse_registers() {
+        assert!(matches!(parse_register("dd"), Ok((None, "dd"))));
+        assert!(matches!(parse_register("\"ap"), Ok((Some('a'), "p"))));
+        assert!(matches!(parse_register("\"2"), Ok((Some('2'), ""))));
+        assert!(matches!(parse_register("\""), Err(ParseError::Incomplete)));
+        assert!(matches!(parse_register("\"0"), Err(ParseError::Invalid)));
+        assert!(matches!(parse_register("\"A"), Err(ParseError::Invalid)));
+    }
+
     #[test]
     fn txo_char() {
         let b = create_line_test_buffer();
@@ -0,0 +1,177 @@
+use winit::keyboard::{Key, NamedKey};
+
+use super::{tree_mode::TreeMode, KeyPress, Mode, ViewState};
+use crate::{
+    model::NodeId,
+    presenter::{PickAction, PickRequest},
+};
+
+/// Score how well `query` fuzzily matches `text`, or None if the characters of `query` don't all
+/// appear in order in `text`. Runs of consecutive characters and matches at the start of words
+/// score higher.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut text_chars = text.chars().flat_map(char::to_lowercase).enumerate();
+    let mut prev_char = ' ';
+    let mut prev_match = None;
+    for qc in query.chars().flat_map(char::to_lowercase) {
+        if qc.is_whitespace() {
+            continue;
+        }
+        loop {
+            let (i, tc) = text_chars.next()?;
+            let word_start = !prev_char.is_alphanumeric();
+            prev_char = tc;
+            if tc == qc {
+                score += 1;
+                if word_start {
+                    score += 3;
+                }
+                if prev_match.is_some_and(|p| p + 1 == i) {
+                    score += 5;
+                }
+                prev_match = Some(i);
+                break;
+            }
+        }
+    }
+    Some(score)
+}
+
+/// A list of nodes to choose from, narrowed down by fuzzily matching a query.
+pub struct Picker {
+    pub action: PickAction,
+    items: Vec<(NodeId, String)>,
+    /// Indices into `items` of the ones that match the query, best first.
+    matches: Vec<usize>,
+    pub query: String,
+    pub selected: usize,
+    /// Whether keys are typed into the query rather than moving the selection.
+    pub typing: bool,
+}
+
+impl Picker {
+    pub fn new(req: PickRequest) -> Picker {
+        let mut p = Picker {
+            action: req.action,
+            items: req.items,
+            matches: Vec::new(),
+            query: String::new(),
+            selected: 0,
+            typing: true,
+        };
+        p.update_matches();
+        p
+    }
+
+    fn update_matches(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, label))| fuzzy_score(&self.query, label).map(|s| (s, i)))
+            .collect();
+        // the sort is stable, so ties stay in tree order
+        scored.sort_by_key(|(s, _)| -s);
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.selected = 0;
+    }
+
+    pub fn push_query(&mut self, s: &str) {
+        self.query.push_str(s);
+        self.update_matches();
+    }
+
+    pub fn pop_query(&mut self) {
+        self.query.pop();
+        self.update_matches();
+    }
+
+    /// The labels of the matching items, best first.
+    pub fn matches(&self) -> impl Iterator<Item = &str> {
+        self.matches.iter().map(|i| self.items[*i].1.as_str())
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        if !self.matches.is_empty() {
+            self.selected = self
+                .selected
+                .saturating_add_signed(delta)
+                .min(self.matches.len() - 1);
+        }
+    }
+
+    pub fn selected_node(&self) -> Option<NodeId> {
+        self.matches.get(self.selected).map(|i| self.items[*i].0)
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        match self.action {
+            PickAction::Refile { .. } => "refile to:",
+            PickAction::Jump => "jump to:",
+        }
+    }
+}
+
+/// Choosing a node with the picker in `ViewState::picker`. Keys are typed into the query, with
+/// up/down or ctrl+j/ctrl+k moving the selection. Tab switches to moving the selection with j/k.
+#[derive(Default)]
+pub struct PickerMode;
+
+impl Mode for PickerMode {
+    fn process_key(&mut self, key: &KeyPress, view_state: &mut ViewState) -> Option<Box<dyn Mode>> {
+        let picker = view_state.picker.as_mut().unwrap();
+        match &key.logical_key {
+            Key::Named(NamedKey::Escape) => {
+                view_state.picker = None;
+                return Some(Box::<TreeMode>::default());
+            }
+            Key::Named(NamedKey::Enter) => {
+                view_state.finish_pick();
+                return Some(Box::<TreeMode>::default());
+            }
+            Key::Named(NamedKey::Tab) => picker.typing = !picker.typing,
+            Key::Named(NamedKey::ArrowDown) => picker.move_selection(1),
+            Key::Named(NamedKey::ArrowUp) => picker.move_selection(-1),
+            Key::Named(NamedKey::Backspace) if picker.typing => picker.pop_query(),
+            Key::Named(NamedKey::Space) if picker.typing => picker.push_query(" "),
+            Key::Character(c) if key.mods.control_key() => match c.as_str() {
+                "j" => picker.move_selection(1),
+                "k" => picker.move_selection(-1),
+                _ => {}
+            },
+            Key::Character(c) if picker.typing => picker.push_query(c.as_str()),
+            Key::Character(c) => match c.as_str() {
+                "j" => picker.move_selection(1),
+                "k" => picker.move_selection(-1),
+                "/" | "i" => picker.typing = true,
+                _ => {}
+            },
+            _ => {}
+        }
+        None
+    }
+
+    fn cursor_shape(&self) -> Option<super::CursorShape> {
+        Some(super::CursorShape::Line)
+    }
+
+    fn name(&self) -> &'static str {
+        "PICK"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_matching() {
+        assert!(fuzzy_score("prj", "Projects > Garden").is_some());
+        assert!(fuzzy_score("jrp", "Projects > Garden").is_none());
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        // consecutive characters at the start of a word beat scattered ones
+        assert!(fuzzy_score("gar", "Projects > Garden") > fuzzy_score("gar", "Go far away"));
+        assert!(fuzzy_score("GAR", "garden").is_some());
+    }
+}
@@ -1,115 +1,257 @@
-use super::{cmd_mode::CmdMode, edit_mode::EditMode, insert_mode::InsertMode, Mode, ViewState};
-
-use winit::{
-    event::{ElementState, KeyEvent},
-    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+use super::{
+    cmd_mode::CmdMode,
+    edit_mode::EditMode,
+    insert_mode::InsertMode,
+    motion::{parse_register, ParseError},
+    picker::PickerMode,
+    tree_motion::TreeCommand,
+    visual_mode::VisualMode,
+    KeyPress, Mode, ViewState,
 };
 
+use winit::keyboard::{Key, KeyCode, ModifiersState, NamedKey, PhysicalKey};
+
 #[derive(Default)]
-pub struct TreeMode;
+pub struct TreeMode {
+    cmd_buffer: String,
+}
 
-impl super::Mode for TreeMode {
-    fn process_key(
+impl TreeMode {
+    /// Keys held with ctrl or alt, which act right away rather than going into the command buffer.
+    fn process_modified_key(
         &mut self,
-        input: &KeyEvent,
+        key: KeyCode,
         mods: &ModifiersState,
         view_state: &mut ViewState,
     ) -> Option<Box<dyn Mode>> {
-        let key = match input.physical_key {
-            PhysicalKey::Code(c) => c,
-            _ => return None,
-        };
+        if mods.control_key() {
+            match key {
+                KeyCode::KeyJ => {
+                    view_state.presenter.swap_node(view_state.cur_node, 1);
+                }
+                KeyCode::KeyK => {
+                    view_state.presenter.swap_node(view_state.cur_node, -1);
+                }
+                KeyCode::KeyL => {
+                    view_state.presenter.indent_node(view_state.cur_node);
+                }
+                KeyCode::KeyH => {
+                    view_state.presenter.make_child_sibling(view_state.cur_node);
+                }
+                KeyCode::KeyP => {
+                    view_state.begin_jump();
+                    return Some(Box::new(PickerMode));
+                }
+                KeyCode::KeyO => view_state.jump_back(),
+                KeyCode::KeyI => view_state.jump_forward(),
+                _ => {}
+            }
+        } else {
+            match key {
+                KeyCode::KeyP => self.put(true, mods.shift_key(), view_state),
+                KeyCode::KeyA => self.put_mirror(true, view_state),
+                _ => {}
+            }
+        }
+        None
+    }
 
-        if input.state == ElementState::Pressed {
-            if mods.contains(ModifiersState::CONTROL) {
-                match key {
-                    KeyCode::KeyJ => {
-                        view_state.presenter.swap_node(view_state.cur_node, 1);
-                    }
-                    KeyCode::KeyK => {
-                        view_state.presenter.swap_node(view_state.cur_node, -1);
-                    }
-                    KeyCode::KeyL => {}
-                    KeyCode::KeyH => {
-                        view_state.presenter.make_child_sibling(view_state.cur_node);
-                    }
-                    _ => {}
+    fn put(&self, as_child: bool, consume: bool, view_state: &mut ViewState) {
+        if let Some(nn) = view_state.presenter.put_node(
+            view_state.cur_node,
+            view_state.register,
+            consume,
+            as_child,
+        ) {
+            view_state.cur_node = nn;
+        }
+    }
+
+    fn put_mirror(&self, as_child: bool, view_state: &mut ViewState) {
+        match view_state
+            .presenter
+            .put_mirror(view_state.cur_node, view_state.register, as_child)
+        {
+            Ok(nn) => view_state.cur_node = nn,
+            Err(e) => view_state.prev_error = Some(e),
+        }
+    }
+
+    /// Keys that aren't part of the motion grammar.
+    fn process_other_key(
+        &mut self,
+        key: char,
+        view_state: &mut ViewState,
+    ) -> Option<Box<dyn Mode>> {
+        match key {
+            'J' => {
+                if let Err(e) = view_state.presenter.join_with_next(view_state.cur_node) {
+                    view_state.prev_error = Some(e);
                 }
-            } else {
-                match key {
-                    KeyCode::KeyJ => {
-                        view_state.move_to_next_child();
-                    }
-                    KeyCode::KeyK => {
-                        view_state.move_to_prev_child();
-                    }
-                    KeyCode::KeyL => {
-                        view_state.enter_node();
-                    }
-                    KeyCode::KeyH => {
-                        view_state.exit_node();
-                    }
-                    KeyCode::KeyI => {
-                        view_state.begin_editing(!mods.shift_key());
-                        return Some(Box::new(InsertMode));
-                    }
-                    KeyCode::KeyE => {
-                        view_state.begin_editing(false);
-                        return Some(Box::<EditMode>::default());
-                    }
-                    KeyCode::KeyC => {
-                        view_state.cur_node = view_state
-                            .presenter
-                            .insert_node_as_child(view_state.cur_node, mods.shift_key());
-                        view_state.begin_editing(false);
-                        return Some(Box::new(InsertMode));
-                    }
-                    KeyCode::KeyO => {
-                        if let Some(nn) = view_state
-                            .presenter
-                            .insert_node_in_parent(view_state.cur_node, !mods.shift_key())
-                        {
-                            view_state.cur_node = nn;
-                            view_state.begin_editing(false);
-                            return Some(Box::new(InsertMode));
-                        }
-                    }
-                    KeyCode::KeyX => {
-                        let nc = view_state.presenter.model().next_child(view_state.cur_node);
-                        if let Some(nn) = view_state.presenter.delete_node(view_state.cur_node) {
-                            view_state.cur_node = nc.unwrap_or(nn);
-                        }
-                    }
-                    KeyCode::KeyY => {
-                        view_state.presenter.copy_node(view_state.cur_node);
-                    }
-                    KeyCode::KeyP => {
-                        if let Some(nn) = view_state.presenter.put_node(
-                            view_state.cur_node,
-                            mods.contains(ModifiersState::SHIFT),
-                            mods.contains(ModifiersState::ALT),
-                        ) {
-                            view_state.cur_node = nn;
-                        }
-                    }
-                    KeyCode::KeyF => {
-                        view_state.toggle_folded();
-                    }
-                    KeyCode::Minus => {
-                        view_state.presenter.toggle_strikeout(view_state.cur_node);
-                    }
-                    KeyCode::KeyR => {
-                        view_state.presenter.set_current_root(view_state.cur_node);
+            }
+            'i' | 'I' => {
+                view_state.begin_editing(key == 'i');
+                return Some(Box::new(InsertMode));
+            }
+            'e' => {
+                view_state.begin_editing(false);
+                return Some(Box::<EditMode>::default());
+            }
+            'c' | 'C' => {
+                view_state.cur_node = view_state
+                    .presenter
+                    .insert_node_as_child(view_state.cur_node, key == 'C');
+                view_state.begin_editing(false);
+                return Some(Box::new(InsertMode));
+            }
+            'o' | 'O' => {
+                if let Some(nn) = view_state
+                    .presenter
+                    .insert_node_in_parent(view_state.cur_node, key == 'o')
+                {
+                    view_state.cur_node = nn;
+                    view_state.begin_editing(false);
+                    return Some(Box::new(InsertMode));
+                }
+            }
+            'p' | 'P' => self.put(false, key == 'P', view_state),
+            'a' => self.put_mirror(false, view_state),
+            't' => {
+                view_state.presenter.toggle_clock(view_state.cur_node);
+            }
+            'F' => {
+                if let Err(e) = view_state.presenter.flatten_node(view_state.cur_node) {
+                    view_state.prev_error = Some(e);
+                }
+            }
+            'w' => match view_state.presenter.wrap_nodes(&[view_state.cur_node]) {
+                Ok(wrapper) => {
+                    view_state.cur_node = wrapper;
+                    view_state.begin_editing(false);
+                    return Some(Box::new(InsertMode));
+                }
+                Err(e) => view_state.prev_error = Some(e),
+            },
+            'r' => view_state.narrow(),
+            'R' => view_state.widen(),
+            'v' => {
+                view_state.begin_visual();
+                return Some(Box::new(VisualMode));
+            }
+            ':' => {
+                view_state.begin_command_edit();
+                return Some(Box::<CmdMode>::default());
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl TreeMode {
+    fn run(&mut self, cmd: TreeCommand, view_state: &mut ViewState) -> Option<Box<dyn Mode>> {
+        match cmd {
+            TreeCommand::Move(motion) => view_state.move_by(motion),
+            TreeCommand::Operate { op, operand } => {
+                let nodes = view_state.operand_nodes(operand);
+                view_state.apply_operator(op, &nodes);
+            }
+            TreeCommand::SetMark(name) => view_state.presenter.set_mark(name, view_state.cur_node),
+            TreeCommand::GoToMark(name) => view_state.jump_to_mark(name),
+            TreeCommand::Fold(fold) => view_state.fold(fold),
+            TreeCommand::Repeat(count) => self.repeat_change(count, view_state),
+            TreeCommand::Record(reg) => view_state.begin_recording(reg),
+            TreeCommand::Play { count, reg } => view_state.play_macro(reg, count),
+            TreeCommand::Other { key, .. } => return self.process_other_key(key, view_state),
+        }
+        None
+    }
+
+    /// Run the last change again, typing the same text into the node if it started inserting.
+    fn repeat_change(&mut self, count: Option<usize>, view_state: &mut ViewState) {
+        let change = match view_state.last_tree_change.clone() {
+            Some(c) => c,
+            None => return,
+        };
+        let cmd = match count {
+            Some(n) => change.cmd.with_count(n),
+            None => change.cmd,
+        };
+        let times = match cmd {
+            TreeCommand::Other { count, .. } => count,
+            _ => 1,
+        };
+        // a register given to `.` is used instead of the one the change used
+        let reg = view_state.register;
+        view_state.register = reg.or(change.register);
+        for _ in 0..times {
+            self.run(cmd, view_state);
+            view_state.record_tree_change(cmd);
+            if view_state.cur_edit.is_some() {
+                view_state.replay_insert(change.inserted.as_deref());
+                view_state.finish_editing();
+            }
+        }
+        view_state.register = reg;
+    }
+}
+
+impl super::Mode for TreeMode {
+    fn process_key(
+        &mut self,
+        input: &KeyPress,
+        view_state: &mut ViewState,
+    ) -> Option<Box<dyn Mode>> {
+        if input.mods.control_key() || input.mods.alt_key() {
+            // a register typed first still applies, like `"a` before alt+p
+            view_state.register = parse_register(&self.cmd_buffer)
+                .ok()
+                .and_then(|(reg, _)| reg);
+            self.cmd_buffer.clear();
+            let next_mode = match input.physical_key {
+                PhysicalKey::Code(key) => self.process_modified_key(key, &input.mods, view_state),
+                _ => None,
+            };
+            view_state.register = None;
+            return next_mode;
+        }
+        match &input.logical_key {
+            Key::Named(NamedKey::Escape) => {
+                self.cmd_buffer.clear();
+                None
+            }
+            Key::Character(ch)
+                if ch == "q" && self.cmd_buffer.is_empty() && view_state.recording().is_some() =>
+            {
+                view_state.stop_recording();
+                None
+            }
+            Key::Character(ch) => {
+                self.cmd_buffer.push_str(ch.as_str());
+                let parsed = parse_register(&self.cmd_buffer)
+                    .and_then(|(reg, cmd)| Ok((reg, TreeCommand::parse(cmd)?)));
+                match parsed {
+                    Ok((reg, cmd)) => {
+                        self.cmd_buffer.clear();
+                        view_state.register = reg;
+                        let next_mode = self.run(cmd, view_state);
+                        view_state.record_tree_change(cmd);
+                        view_state.register = None;
+                        next_mode
                     }
-                    KeyCode::Semicolon if mods.contains(ModifiersState::SHIFT) => {
-                        view_state.begin_command_edit();
-                        return Some(Box::<CmdMode>::default());
+                    Err(ParseError::Unknown) | Err(ParseError::Invalid) => {
+                        view_state.prev_error = Some(anyhow::anyhow!(
+                            "unknown/invalid command: {}",
+                            self.cmd_buffer
+                        ));
+                        self.cmd_buffer.clear();
+                        None
                     }
-                    _ => {}
+                    Err(ParseError::Incomplete) => None,
                 }
             }
+            _ => None,
         }
-        None
     }
 
     fn name(&self) -> &'static str {
@@ -0,0 +1,423 @@
+use super::motion::ParseError;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TreeMotionType {
+    NextSibling,
+    PrevSibling,
+    Parent,
+    FirstChild,
+    LastChild,
+    /// The next node on screen, going into children unless they are folded.
+    NextPreorder,
+    /// The previous node on screen.
+    PrevPreorder,
+    ParentNextSibling,
+    ParentPrevSibling,
+    /// The current root, at the top of the screen.
+    First,
+    /// The last node on screen.
+    Last,
+}
+
+impl TreeMotionType {
+    /// Whether the motion goes towards the end of the tree.
+    pub fn forward(&self) -> bool {
+        matches!(
+            self,
+            TreeMotionType::NextSibling
+                | TreeMotionType::FirstChild
+                | TreeMotionType::LastChild
+                | TreeMotionType::NextPreorder
+                | TreeMotionType::ParentNextSibling
+                | TreeMotionType::Last
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct TreeMotion {
+    pub count: usize,
+    pub mo: TreeMotionType,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TreeOperator {
+    Cut,
+    Copy,
+    Strikeout,
+    Fold,
+    Indent,
+    Outdent,
+}
+
+/// The nodes an operator acts on.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Operand {
+    /// The nodes from the cursor to wherever the motion ends up.
+    Motion(TreeMotion),
+    /// The current node and the siblings after it, this many in total.
+    Siblings(usize),
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum FoldCommand {
+    /// Fold everything below the current root.
+    All,
+    /// Unfold everything.
+    Nothing,
+    /// Show this many levels below the current root.
+    ToDepth(usize),
+    /// Fold the siblings of the current node.
+    Siblings,
+    /// Unfold the nodes above the current node.
+    PathToCursor,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TreeCommand {
+    Move(TreeMotion),
+    Operate {
+        op: TreeOperator,
+        operand: Operand,
+    },
+    SetMark(char),
+    GoToMark(char),
+    Fold(FoldCommand),
+    /// Repeat the last change, with a new count if one is given.
+    Repeat(Option<usize>),
+    /// Start recording keys into a macro register.
+    Record(char),
+    /// Play a macro `count` times, or the last one played if `reg` is None.
+    Play {
+        count: usize,
+        reg: Option<char>,
+    },
+    /// Any other key, which tree mode handles itself.
+    Other {
+        count: usize,
+        key: char,
+    },
+}
+
+/// Parse a count prefix from the start of `c`, returning it and the first character after it.
+fn parse_count(c: &mut impl Iterator<Item = char>) -> Result<(Option<usize>, char), ParseError> {
+    let mut ch = c.next().ok_or(ParseError::Incomplete)?;
+    // a leading zero isn't a count
+    if ch == '0' || !ch.is_ascii_digit() {
+        return Ok((None, ch));
+    }
+    let mut num = 0;
+    while let Some(d) = ch.to_digit(10) {
+        num = num * 10 + d as usize;
+        ch = c.next().ok_or(ParseError::Incomplete)?;
+    }
+    Ok((Some(num), ch))
+}
+
+/// Parse the motion starting with `ch`, taking any further keys it needs from `c`.
+fn parse_motion_type(
+    ch: char,
+    c: &mut impl Iterator<Item = char>,
+) -> Result<TreeMotionType, ParseError> {
+    Ok(match ch {
+        'j' => TreeMotionType::NextPreorder,
+        'k' => TreeMotionType::PrevPreorder,
+        'h' => TreeMotionType::Parent,
+        'l' => TreeMotionType::FirstChild,
+        'L' => TreeMotionType::LastChild,
+        '}' => TreeMotionType::NextSibling,
+        '{' => TreeMotionType::PrevSibling,
+        ']' => TreeMotionType::ParentNextSibling,
+        '[' => TreeMotionType::ParentPrevSibling,
+        'G' => TreeMotionType::Last,
+        'g' => match c.next() {
+            Some('g') => TreeMotionType::First,
+            Some(_) => return Err(ParseError::Unknown),
+            None => return Err(ParseError::Incomplete),
+        },
+        _ => return Err(ParseError::Unknown),
+    })
+}
+
+impl TreeCommand {
+    /// Whether the command changes the tree, so that `.` can repeat it.
+    pub fn is_change(&self) -> bool {
+        match self {
+            TreeCommand::Operate { op, .. } => {
+                !matches!(op, TreeOperator::Copy | TreeOperator::Fold)
+            }
+            TreeCommand::Other { key, .. } => "iIcCoOpPaFJw".contains(*key),
+            _ => false,
+        }
+    }
+
+    /// The same command with its count replaced by `count`.
+    pub fn with_count(self, count: usize) -> TreeCommand {
+        match self {
+            TreeCommand::Operate {
+                op,
+                operand: Operand::Siblings(_),
+            } => TreeCommand::Operate {
+                op,
+                operand: Operand::Siblings(count),
+            },
+            TreeCommand::Operate {
+                op,
+                operand: Operand::Motion(m),
+            } => TreeCommand::Operate {
+                op,
+                operand: Operand::Motion(TreeMotion { count, mo: m.mo }),
+            },
+            TreeCommand::Other { key, .. } => TreeCommand::Other { count, key },
+            cmd => cmd,
+        }
+    }
+
+    /// Parse a tree mode command like `5j`, `d3j`, `yk`, `>>` or `3x`.
+    ///
+    /// Operators are `d` (cut), `y` (copy), `g-` (strikeout), `zf` (fold), `>` and `<`, followed
+    /// by a motion, or by their last key again to act on the current node. `x`, `-` and `f` act on
+    /// the current node right away, or on the next few siblings with a count.
+    pub fn parse(cmd: &str) -> Result<TreeCommand, ParseError> {
+        let mut c = cmd.chars();
+        let (count, ch) = parse_count(&mut c)?;
+
+        // `g` starts both `gg` and `g-`
+        let strikeout = ch == 'g' && c.clone().next() == Some('-');
+        if !strikeout {
+            match parse_motion_type(ch, &mut c) {
+                Ok(mo) => {
+                    return Ok(TreeCommand::Move(TreeMotion {
+                        count: count.unwrap_or(1),
+                        mo,
+                    }))
+                }
+                Err(ParseError::Unknown) if ch != 'g' => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let op = match ch {
+            'm' | '\'' | 'q' | '@' => {
+                let name = c.next().ok_or(ParseError::Incomplete)?;
+                if ch == '@' && name == '@' {
+                    return Ok(TreeCommand::Play {
+                        count: count.unwrap_or(1),
+                        reg: None,
+                    });
+                }
+                if !name.is_alphabetic() {
+                    return Err(ParseError::Invalid);
+                }
+                return Ok(match ch {
+                    'm' => TreeCommand::SetMark(name),
+                    '\'' => TreeCommand::GoToMark(name),
+                    'q' => TreeCommand::Record(name),
+                    _ => TreeCommand::Play {
+                        count: count.unwrap_or(1),
+                        reg: Some(name),
+                    },
+                });
+            }
+            'd' => TreeOperator::Cut,
+            'y' => TreeOperator::Copy,
+            '>' => TreeOperator::Indent,
+            '<' => TreeOperator::Outdent,
+            'g' => {
+                c.next();
+                TreeOperator::Strikeout
+            }
+            'z' => {
+                let fold = match c.next() {
+                    Some('f') => None,
+                    Some('M') => Some(FoldCommand::All),
+                    Some('R') => Some(FoldCommand::Nothing),
+                    Some('s') => Some(FoldCommand::Siblings),
+                    Some('v') => Some(FoldCommand::PathToCursor),
+                    Some(d @ '0'..='9') => Some(FoldCommand::ToDepth(d as usize - '0' as usize)),
+                    Some(_) => return Err(ParseError::Unknown),
+                    None => return Err(ParseError::Incomplete),
+                };
+                match fold {
+                    Some(fold) => return Ok(TreeCommand::Fold(fold)),
+                    None => TreeOperator::Fold,
+                }
+            }
+            '.' => return Ok(TreeCommand::Repeat(count)),
+            immediate @ ('x' | '-' | 'f') => {
+                return Ok(TreeCommand::Operate {
+                    op: match immediate {
+                        'x' => TreeOperator::Cut,
+                        '-' => TreeOperator::Strikeout,
+                        _ => TreeOperator::Fold,
+                    },
+                    operand: Operand::Siblings(count.unwrap_or(1)),
+                })
+            }
+            key => {
+                return Ok(TreeCommand::Other {
+                    count: count.unwrap_or(1),
+                    key,
+                })
+            }
+        };
+        let last_op_char = cmd[..cmd.len() - c.as_str().len()].chars().last().unwrap();
+
+        let (motion_count, ch) = parse_count(&mut c)?;
+        let count = count.unwrap_or(1) * motion_count.unwrap_or(1);
+        let operand = if ch == last_op_char {
+            Operand::Siblings(count)
+        } else {
+            Operand::Motion(TreeMotion {
+                count,
+                mo: match parse_motion_type(ch, &mut c) {
+                    Err(ParseError::Unknown) => return Err(ParseError::Invalid),
+                    mo => mo?,
+                },
+            })
+        };
+        if c.next().is_some() {
+            return Err(ParseError::Invalid);
+        }
+        Ok(TreeCommand::Operate { op, operand })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motion(count: usize, mo: TreeMotionType) -> TreeMotion {
+        TreeMotion { count, mo }
+    }
+
+    #[test]
+    fn parse_moves() {
+        assert_eq!(
+            TreeCommand::parse("j").unwrap(),
+            TreeCommand::Move(motion(1, TreeMotionType::NextPreorder))
+        );
+        assert_eq!(
+            TreeCommand::parse("12}").unwrap(),
+            TreeCommand::Move(motion(12, TreeMotionType::NextSibling))
+        );
+        assert_eq!(
+            TreeCommand::parse("gg").unwrap(),
+            TreeCommand::Move(motion(1, TreeMotionType::First))
+        );
+        assert!(matches!(
+            TreeCommand::parse("g"),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(
+            TreeCommand::parse("5"),
+            Err(ParseError::Incomplete)
+        ));
+        assert_eq!(TreeCommand::parse("ma").unwrap(), TreeCommand::SetMark('a'));
+        assert_eq!(
+            TreeCommand::parse("'a").unwrap(),
+            TreeCommand::GoToMark('a')
+        );
+        assert!(matches!(
+            TreeCommand::parse("m"),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(TreeCommand::parse("m1"), Err(ParseError::Invalid)));
+        assert_eq!(
+            TreeCommand::parse("z2").unwrap(),
+            TreeCommand::Fold(FoldCommand::ToDepth(2))
+        );
+        assert_eq!(
+            TreeCommand::parse("zM").unwrap(),
+            TreeCommand::Fold(FoldCommand::All)
+        );
+        assert_eq!(TreeCommand::parse(".").unwrap(), TreeCommand::Repeat(None));
+        assert_eq!(
+            TreeCommand::parse("4.").unwrap(),
+            TreeCommand::Repeat(Some(4))
+        );
+        assert_eq!(TreeCommand::parse("qa").unwrap(), TreeCommand::Record('a'));
+        assert_eq!(
+            TreeCommand::parse("3@a").unwrap(),
+            TreeCommand::Play {
+                count: 3,
+                reg: Some('a')
+            }
+        );
+        assert_eq!(
+            TreeCommand::parse("@@").unwrap(),
+            TreeCommand::Play {
+                count: 1,
+                reg: None
+            }
+        );
+        assert!(matches!(TreeCommand::parse("q@"), Err(ParseError::Invalid)));
+        assert_eq!(
+            TreeCommand::parse("3i").unwrap(),
+            TreeCommand::Other { count: 3, key: 'i' }
+        );
+    }
+
+    #[test]
+    fn parse_operators() {
+        assert_eq!(
+            TreeCommand::parse("d3}").unwrap(),
+            TreeCommand::Operate {
+                op: TreeOperator::Cut,
+                operand: Operand::Motion(motion(3, TreeMotionType::NextSibling)),
+            }
+        );
+        assert_eq!(
+            TreeCommand::parse("2y3k").unwrap(),
+            TreeCommand::Operate {
+                op: TreeOperator::Copy,
+                operand: Operand::Motion(motion(6, TreeMotionType::PrevPreorder)),
+            }
+        );
+        assert_eq!(
+            TreeCommand::parse(">>").unwrap(),
+            TreeCommand::Operate {
+                op: TreeOperator::Indent,
+                operand: Operand::Siblings(1),
+            }
+        );
+        assert_eq!(
+            TreeCommand::parse("g-2-").unwrap(),
+            TreeCommand::Operate {
+                op: TreeOperator::Strikeout,
+                operand: Operand::Siblings(2),
+            }
+        );
+        assert_eq!(
+            TreeCommand::parse("zfL").unwrap(),
+            TreeCommand::Operate {
+                op: TreeOperator::Fold,
+                operand: Operand::Motion(motion(1, TreeMotionType::LastChild)),
+            }
+        );
+        assert_eq!(
+            TreeCommand::parse("dgg").unwrap(),
+            TreeCommand::Operate {
+                op: TreeOperator::Cut,
+                operand: Operand::Motion(motion(1, TreeMotionType::First)),
+            }
+        );
+        assert_eq!(
+            TreeCommand::parse("4x").unwrap(),
+            TreeCommand::Operate {
+                op: TreeOperator::Cut,
+                operand: Operand::Siblings(4),
+            }
+        );
+        assert!(matches!(
+            TreeCommand::parse("d"),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(
+            TreeCommand::parse("d2"),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(TreeCommand::parse("dq"), Err(ParseError::Invalid)));
+        assert!(matches!(TreeCommand::parse("gq"), Err(ParseError::Unknown)));
+    }
+}
@@ -1,9 +1,50 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 
 use ropey::Rope;
 
-use super::{motion::Command, Mode};
-use crate::{model::NodeId, presenter::Presenter, view::insert_mode::InsertMode};
+use super::{
+    keys::{format_keys, parse_keys},
+    motion::Command,
+    picker::Picker,
+    tree_motion::{FoldCommand, Operand, TreeCommand, TreeMotion, TreeMotionType, TreeOperator},
+    KeyPress, Mode,
+};
+use crate::{
+    model::{NodeId, Tree, ROOT_PARENT_ID},
+    presenter::{node_paths, PickAction, PickRequest, Presenter},
+    view::insert_mode::InsertMode,
+};
+
+/// A place the cursor has been, kept in the jump list.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Jump {
+    root: NodeId,
+    node: NodeId,
+}
+
+const MAX_JUMPS: usize = 100;
+
+/// A change that `.` can repeat: a tree or edit mode command and the register it used, along
+/// with the text typed into the node afterwards if it started inserting.
+#[derive(Clone)]
+pub struct Change<C> {
+    pub cmd: C,
+    pub register: Option<char>,
+    pub inserted: Option<String>,
+}
+
+/// A change that started inserting text, which is recorded once the insert finishes.
+enum PendingChange {
+    Tree(TreeCommand, Option<char>),
+    Edit(Command, Option<char>),
+}
+
+/// The nodes selected in visual mode: the siblings between `anchor` and the cursor, along with
+/// any nodes marked individually.
+pub struct Visual {
+    pub anchor: NodeId,
+    pub marked: Vec<NodeId>,
+}
 
 // TODO: should this just be part of the presenter?
 pub struct ViewState {
@@ -13,6 +54,24 @@ pub struct ViewState {
     pub cur_cmd: Option<(usize, Rope)>,
     pub prev_error: Option<anyhow::Error>,
     pub folded_nodes: HashSet<NodeId>,
+    pub picker: Option<Picker>,
+    pub visual: Option<Visual>,
+    /// The register chosen for the command being run, like `"a`, which cuts, copies and puts
+    /// use instead of the top of the snip stack.
+    pub register: Option<char>,
+    jumps: Vec<Jump>,
+    /// Where in `jumps` ctrl+o and ctrl+i go from, or its length if they haven't been used since
+    /// the last jump.
+    jump_index: usize,
+    pub last_tree_change: Option<Change<TreeCommand>>,
+    pub last_edit_change: Option<Change<Command>>,
+    /// The change that started the current insert, and the text inserted so far.
+    pending_change: Option<(PendingChange, String)>,
+    /// The register a macro is being recorded into, and the keys pressed so far.
+    recording: Option<(char, Vec<KeyPress>)>,
+    /// Keys from macros that are still to be played.
+    playback: VecDeque<KeyPress>,
+    last_macro: Option<char>,
 }
 
 impl ViewState {
@@ -24,57 +83,239 @@ impl ViewState {
             cur_cmd: None,
             prev_error: None,
             folded_nodes: HashSet::new(),
+            picker: None,
+            visual: None,
+            register: None,
+            jumps: Vec::new(),
+            jump_index: 0,
+            last_tree_change: None,
+            last_edit_change: None,
+            pending_change: None,
+            recording: None,
+            playback: VecDeque::new(),
+            last_macro: None,
         }
     }
 
-    pub fn move_to_next_child(&mut self) {
-        if let Some(next_child) = self
-            .presenter
-            .model()
-            .next_child(self.cur_node)
-            .or_else(|| {
-                self.presenter
-                    .model()
-                    .node(self.cur_node)
-                    .children
-                    .first()
-                    .copied()
-            })
-        {
-            self.cur_node = next_child;
+    /// Step from `start` using `step` until reaching a node that isn't hidden by the filter.
+    fn skip_hidden(
+        &self,
+        start: Option<NodeId>,
+        step: impl Fn(&Tree, NodeId) -> Option<NodeId>,
+    ) -> Option<NodeId> {
+        let mut cur = start;
+        while let Some(n) = cur {
+            if self.presenter.node_visible(n) {
+                return Some(n);
+            }
+            cur = step(self.presenter.model(), n);
         }
+        None
     }
 
-    pub fn move_to_prev_child(&mut self) {
-        if let Some(prev_child) = self.presenter.model().prev_child(self.cur_node) {
-            self.cur_node = prev_child;
-        } else {
-            // TODO: sometimes when the current root is not the tree global root we
-            // should be exiting up a level and changing the current root, but we don't
-            // because prev_child() doesn't stop until it gets to the global root.
-            // Some kind of tree slice could probably fix this?
-            self.exit_node();
+    /// The first child of `node` that the cursor can move to. The cursor doesn't go into a
+    /// mirror's children, since they are the mirrored node's own children and moving back up
+    /// would end up under the mirrored node instead.
+    fn first_visible_child(&self, node: NodeId) -> Option<NodeId> {
+        let model = self.presenter.model();
+        if model.is_mirror(node) {
+            return None;
         }
+        self.skip_hidden(
+            model.content(node).children.first().copied(),
+            Tree::next_child,
+        )
     }
 
-    pub fn enter_node(&mut self) {
-        if let Some(enter_node) = self.presenter.model().node(self.cur_node).children.first() {
-            self.cur_node = *enter_node;
+    fn last_visible_child(&self, node: NodeId) -> Option<NodeId> {
+        let model = self.presenter.model();
+        if model.is_mirror(node) {
+            return None;
         }
+        self.skip_hidden(
+            model.content(node).children.last().copied(),
+            Tree::prev_child,
+        )
     }
 
-    pub fn exit_node(&mut self) {
-        if let Some(exit_node) = self.presenter.model().node(self.cur_node).parent() {
-            if self.cur_node == self.presenter.current_root() {
-                self.presenter.set_current_root(exit_node);
+    /// The node after `node` in preorder, not leaving the current root and skipping the
+    /// children of folded nodes unless `into_folded`.
+    fn next_in_preorder(&self, node: NodeId, into_folded: bool) -> Option<NodeId> {
+        if into_folded || !self.folded_nodes.contains(&node) {
+            if let Some(child) = self.first_visible_child(node) {
+                return Some(child);
+            }
+        }
+        let model = self.presenter.model();
+        let mut cur = node;
+        while cur != self.presenter.current_root() {
+            if let Some(next) = self.skip_hidden(model.next_child(cur), Tree::next_child) {
+                return Some(next);
+            }
+            cur = model.node(cur).parent()?;
+        }
+        None
+    }
+
+    /// The node before `node` in preorder, as it appears on screen.
+    fn prev_in_preorder(&self, node: NodeId) -> Option<NodeId> {
+        if node == self.presenter.current_root() {
+            return None;
+        }
+        let model = self.presenter.model();
+        match self.skip_hidden(model.prev_child(node), Tree::prev_child) {
+            Some(mut prev) => {
+                while !self.folded_nodes.contains(&prev) {
+                    match self.last_visible_child(prev) {
+                        Some(child) => prev = child,
+                        None => break,
+                    }
+                }
+                Some(prev)
+            }
+            None => model.node(node).parent(),
+        }
+    }
+
+    /// Where one step of `mo` goes from `node` without changing the current root, if anywhere.
+    fn motion_step(&self, node: NodeId, mo: TreeMotionType) -> Option<NodeId> {
+        let model = self.presenter.model();
+        let root = self.presenter.current_root();
+        match mo {
+            // the current root's siblings and parent aren't on screen
+            TreeMotionType::NextSibling | TreeMotionType::PrevSibling | TreeMotionType::Parent
+                if node == root =>
+            {
+                None
+            }
+            TreeMotionType::NextSibling => {
+                self.skip_hidden(model.next_child(node), Tree::next_child)
+            }
+            TreeMotionType::PrevSibling => {
+                self.skip_hidden(model.prev_child(node), Tree::prev_child)
+            }
+            TreeMotionType::Parent => model.node(node).parent(),
+            TreeMotionType::FirstChild | TreeMotionType::LastChild
+                if self.folded_nodes.contains(&node) =>
+            {
+                None
+            }
+            TreeMotionType::FirstChild => self.first_visible_child(node),
+            TreeMotionType::LastChild => self.last_visible_child(node),
+            TreeMotionType::NextPreorder => self.next_in_preorder(node, false),
+            TreeMotionType::PrevPreorder => self.prev_in_preorder(node),
+            TreeMotionType::ParentNextSibling => {
+                let parent = self.motion_step(node, TreeMotionType::Parent)?;
+                self.motion_step(parent, TreeMotionType::NextSibling)
+            }
+            TreeMotionType::ParentPrevSibling => {
+                let parent = self.motion_step(node, TreeMotionType::Parent)?;
+                self.motion_step(parent, TreeMotionType::PrevSibling)
+            }
+            TreeMotionType::First => Some(root).filter(|r| *r != node),
+            TreeMotionType::Last => {
+                let mut last = root;
+                while !self.folded_nodes.contains(&last) {
+                    match self.last_visible_child(last) {
+                        Some(child) => last = child,
+                        None => break,
+                    }
+                }
+                Some(last).filter(|l| *l != node)
+            }
+        }
+    }
+
+    /// Move the cursor by `motion`, stopping early if it can't go any further.
+    pub fn move_by(&mut self, motion: TreeMotion) {
+        if matches!(motion.mo, TreeMotionType::First | TreeMotionType::Last) {
+            self.record_jump();
+        }
+        for _ in 0..motion.count {
+            match self.motion_step(self.cur_node, motion.mo) {
+                Some(n) => self.cur_node = n,
+                None => break,
+            }
+        }
+    }
+
+    /// The nodes that `operand` covers from the cursor, in tree order and leaving out any that
+    /// are inside others.
+    pub fn operand_nodes(&self, operand: Operand) -> Vec<NodeId> {
+        let model = self.presenter.model();
+        match operand {
+            Operand::Siblings(count) => {
+                let mut nodes = vec![self.cur_node];
+                while nodes.len() < count {
+                    match self.motion_step(*nodes.last().unwrap(), TreeMotionType::NextSibling) {
+                        Some(n) => nodes.push(n),
+                        None => break,
+                    }
+                }
+                nodes
+            }
+            Operand::Motion(motion) => {
+                let mut target = self.cur_node;
+                for _ in 0..motion.count {
+                    match self.motion_step(target, motion.mo) {
+                        Some(n) => target = n,
+                        None => break,
+                    }
+                }
+                let (start, end) = if motion.mo.forward() {
+                    (self.cur_node, target)
+                } else {
+                    (target, self.cur_node)
+                };
+                // everything in between in preorder, which covers siblings, parents and children
+                let mut nodes = vec![start];
+                while *nodes.last().unwrap() != end {
+                    match self.next_in_preorder(*nodes.last().unwrap(), true) {
+                        Some(n) => nodes.push(n),
+                        None => return vec![self.cur_node],
+                    }
+                }
+                model.top_level_in_order(&nodes)
+            }
+        }
+    }
+
+    /// Apply `op` to each of `nodes`.
+    pub fn apply_operator(&mut self, op: TreeOperator, nodes: &[NodeId]) {
+        match op {
+            TreeOperator::Cut => {
+                if let Some(nn) = self.presenter.delete_nodes(nodes, self.register) {
+                    self.cur_node = nn;
+                }
+            }
+            TreeOperator::Copy => self.presenter.copy_nodes(nodes, self.register),
+            TreeOperator::Strikeout => {
+                for n in nodes {
+                    if let Err(e) = self.presenter.toggle_strikeout(*n) {
+                        self.prev_error = Some(e);
+                    }
+                }
+            }
+            TreeOperator::Fold => {
+                self.toggle_folded_nodes(nodes);
+                self.move_out_of_folds();
+            }
+            TreeOperator::Indent => {
+                for n in nodes {
+                    self.presenter.indent_node(*n);
+                }
+            }
+            TreeOperator::Outdent => {
+                for n in nodes {
+                    self.presenter.make_child_sibling(*n);
+                }
             }
-            self.cur_node = exit_node;
         }
     }
 
     pub fn begin_editing(&mut self, start_at_end: bool) {
         assert!(self.cur_edit.is_none());
-        let text = &self.presenter.model().node(self.cur_node).text;
+        let text = &self.presenter.model().content(self.cur_node).text;
         self.cur_edit = Some((
             if start_at_end { text.len() } else { 0 },
             Rope::from_str(text),
@@ -87,6 +328,27 @@ impl ViewState {
             .update_node_text(self.cur_node, new_text.to_string());
     }
 
+    /// Split the node being edited in two at the cursor, carrying on editing the second half.
+    pub fn split_node(&mut self) {
+        let (cursor_index, buf) = self.cur_edit.take().expect("was editing");
+        let split_at = cursor_index.min(buf.len_chars());
+        let before = buf.slice(..split_at).to_string();
+        let after = buf.slice(split_at..).to_string();
+        match self
+            .presenter
+            .split_node(self.cur_node, before, after.clone())
+        {
+            Ok(new_node) => {
+                self.cur_node = new_node;
+                self.cur_edit = Some((0, Rope::from_str(&after)));
+            }
+            Err(e) => {
+                self.cur_edit = Some((cursor_index, buf));
+                self.prev_error = Some(e);
+            }
+        }
+    }
+
     pub fn begin_command_edit(&mut self) {
         assert!(self.cur_cmd.is_none());
         self.cur_cmd = Some((0, Rope::new()));
@@ -94,19 +356,277 @@ impl ViewState {
 
     pub fn abort_command_edit(&mut self) {
         self.cur_cmd = None;
+        self.presenter.cancel_prompt();
     }
 
     pub fn process_command(&mut self) {
         let (_, cmd_rope) = self.cur_cmd.take().expect("was editing a command");
-        match self.presenter.process_command(cmd_rope.into()) {
-            Ok(()) => {}
+        let before = self.here();
+        match self
+            .presenter
+            .process_command(self.cur_node, cmd_rope.into())
+        {
+            Ok(Some(new_cursor)) => {
+                self.cur_node = new_cursor;
+            }
+            Ok(None) => {}
             Err(e) => {
                 self.prev_error = Some(e);
             }
         }
+        if self.presenter.current_root() != before.root {
+            self.push_jump(before);
+        }
+        // keep the command line open if the command asked a question
+        if self.presenter.prompt().is_some() {
+            self.begin_command_edit();
+        }
+        if let Some(req) = self.presenter.take_pick_request() {
+            self.picker = Some(Picker::new(req));
+        }
+    }
+
+    /// Open the picker to jump to any node in the tree, widening the view if the node is outside
+    /// it.
+    pub fn begin_jump(&mut self) {
+        let model = self.presenter.model();
+        let items = node_paths(model, model.root_id(), |n| n != model.root_id());
+        self.picker = Some(Picker::new(PickRequest {
+            action: PickAction::Jump,
+            items,
+        }));
+    }
+
+    /// Move the cursor to `node`, remembering where it was in the jump list.
+    pub fn jump_to(&mut self, node: NodeId) {
+        self.record_jump();
+        self.reveal(node);
+    }
+
+    /// Narrow the view to the subtree under the cursor.
+    pub fn narrow(&mut self) {
+        self.record_jump();
+        self.presenter.narrow(self.cur_node);
+    }
+
+    /// Go back to the view from before the last `narrow`, with the cursor on the root that was
+    /// left.
+    pub fn widen(&mut self) {
+        self.record_jump();
+        if let Some(old_root) = self.presenter.widen() {
+            self.cur_node = old_root;
+        }
+    }
+
+    /// Move the cursor to the node marked `name`.
+    pub fn jump_to_mark(&mut self, name: char) {
+        match self.presenter.model().mark(name) {
+            Some(node) => self.jump_to(node),
+            None => self.prev_error = Some(anyhow::anyhow!("mark {name} isn't set")),
+        }
+    }
+
+    fn here(&self) -> Jump {
+        Jump {
+            root: self.presenter.current_root(),
+            node: self.cur_node,
+        }
+    }
+
+    fn push_jump(&mut self, jump: Jump) {
+        self.jumps.truncate(self.jump_index);
+        if self.jumps.last() != Some(&jump) {
+            self.jumps.push(jump);
+            if self.jumps.len() > MAX_JUMPS {
+                self.jumps.remove(0);
+            }
+        }
+        self.jump_index = self.jumps.len();
+    }
+
+    /// Remember where the cursor is before moving it somewhere far away.
+    pub fn record_jump(&mut self) {
+        self.push_jump(self.here());
+    }
+
+    /// Go back to `jump` if its node is still in the tree.
+    fn restore_jump(&mut self, jump: Jump) -> bool {
+        let model = self.presenter.model();
+        let exists = |n| model.nodes.contains_key(&n) && model.is_attached(n);
+        if !exists(jump.node) {
+            return false;
+        }
+        if exists(jump.root) {
+            self.presenter.set_current_root(jump.root);
+        }
+        self.reveal(jump.node);
+        true
+    }
+
+    /// Go back to where the cursor was before the last jump.
+    pub fn jump_back(&mut self) {
+        if self.jump_index == self.jumps.len() {
+            // remember where we are to be able to come forward again
+            self.record_jump();
+            self.jump_index -= 1;
+        }
+        while self.jump_index > 0 {
+            self.jump_index -= 1;
+            if self.restore_jump(self.jumps[self.jump_index]) {
+                return;
+            }
+        }
+    }
+
+    /// Undo `jump_back`.
+    pub fn jump_forward(&mut self) {
+        while self.jump_index + 1 < self.jumps.len() {
+            self.jump_index += 1;
+            if self.restore_jump(self.jumps[self.jump_index]) {
+                return;
+            }
+        }
+    }
+
+    /// Move the cursor to `node`, widening the view if the node is outside of it and unfolding
+    /// everything above it so that it can be seen.
+    fn reveal(&mut self, node: NodeId) {
+        self.presenter.widen_to(node);
+        self.cur_node = node;
+        self.unfold_path();
+    }
+
+    /// Close the picker and carry out its action on the selected node.
+    pub fn finish_pick(&mut self) {
+        let picker = self.picker.take().expect("was picking");
+        let target = match picker.selected_node() {
+            Some(t) => t,
+            None => return,
+        };
+        match picker.action {
+            PickAction::Refile { nodes, dest } => {
+                match self.presenter.refile(&nodes, target, dest.as_deref()) {
+                    Ok(new_cursor) => self.cur_node = new_cursor,
+                    Err(e) => self.prev_error = Some(e),
+                }
+            }
+            PickAction::Jump => self.jump_to(target),
+        }
+    }
+
+    /// Remember that `cmd` was run in tree mode with the current register, if it is a change. If
+    /// it started inserting text, it is remembered along with the text once the insert finishes.
+    pub fn record_tree_change(&mut self, cmd: TreeCommand) {
+        if !cmd.is_change() {
+            return;
+        }
+        if self.cur_edit.is_some() {
+            self.pending_change = Some((PendingChange::Tree(cmd, self.register), String::new()));
+        } else {
+            self.last_tree_change = Some(Change {
+                cmd,
+                register: self.register,
+                inserted: None,
+            });
+        }
+    }
+
+    /// Record text typed in insert mode as part of the change being made.
+    pub fn record_inserted(&mut self, text: &str) {
+        if let Some((_, inserted)) = self.pending_change.as_mut() {
+            inserted.push_str(text);
+        }
+    }
+
+    pub fn record_backspace(&mut self) {
+        if let Some((_, inserted)) = self.pending_change.as_mut() {
+            inserted.pop();
+        }
+    }
+
+    /// Stop recording the insert in progress, because what it does can't be repeated by typing
+    /// the same text.
+    pub fn abandon_change(&mut self) {
+        self.pending_change = None;
+    }
+
+    /// Finish recording the insert in progress as the last change.
+    pub fn finish_change(&mut self) {
+        match self.pending_change.take() {
+            Some((PendingChange::Tree(cmd, register), inserted)) => {
+                self.last_tree_change = Some(Change {
+                    cmd,
+                    register,
+                    inserted: Some(inserted),
+                });
+            }
+            Some((PendingChange::Edit(cmd, register), inserted)) => {
+                self.last_edit_change = Some(Change {
+                    cmd,
+                    register,
+                    inserted: Some(inserted),
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Type `inserted` into the node being edited as `.` repeats a change, finishing the insert.
+    pub fn replay_insert(&mut self, inserted: Option<&str>) {
+        if self.pending_change.is_none() {
+            return;
+        }
+        if let (Some((cursor_index, buf)), Some(text)) = (self.cur_edit.as_mut(), inserted) {
+            buf.insert(*cursor_index, text);
+            *cursor_index += text.chars().count();
+        }
+        self.finish_change();
+    }
+
+    fn repeat_edit_change(&mut self, count: Option<usize>) {
+        let change = match self.last_edit_change.clone() {
+            Some(c) => c,
+            None => return,
+        };
+        let (cmd, times) = match count {
+            Some(n) => match change.cmd.with_count(n) {
+                Some(cmd) => (cmd, 1),
+                None => (change.cmd, n),
+            },
+            None => (change.cmd, 1),
+        };
+        // a register given to `.` is used instead of the one the change used
+        let reg = self.register;
+        self.register = reg.or(change.register);
+        for _ in 0..times {
+            self.process_normal_cmd(cmd);
+            self.replay_insert(change.inserted.as_deref());
+        }
+        self.register = reg;
     }
 
     pub fn process_normal_cmd(&mut self, cmd: Command) -> Option<Box<dyn Mode>> {
+        if let Command::Repeat(count) = cmd {
+            self.repeat_edit_change(count);
+            return None;
+        }
+        let next_mode = self.run_normal_cmd(cmd);
+        if cmd.is_change() {
+            if next_mode.is_some() {
+                self.pending_change =
+                    Some((PendingChange::Edit(cmd, self.register), String::new()));
+            } else {
+                self.last_edit_change = Some(Change {
+                    cmd,
+                    register: self.register,
+                    inserted: None,
+                });
+            }
+        }
+        next_mode
+    }
+
+    fn run_normal_cmd(&mut self, cmd: Command) -> Option<Box<dyn Mode>> {
         use super::motion::*;
         let (cursor_index, buf) = self.cur_edit.as_mut().unwrap();
         match cmd {
@@ -119,21 +639,25 @@ impl ViewState {
             }
             Command::Change(m) => {
                 let r = m.range(buf, *cursor_index, 1, &mut None);
-                self.presenter.copy_str(buf.slice(r.clone()).to_string());
+                self.presenter
+                    .copy_str(buf.slice(r.clone()).to_string(), self.register);
                 buf.remove(r);
                 return Some(Box::new(InsertMode));
             }
             Command::Delete(m) => {
                 let r = m.range(buf, *cursor_index, 1, &mut None);
-                self.presenter.copy_str(buf.slice(r.clone()).to_string());
+                self.presenter
+                    .copy_str(buf.slice(r.clone()).to_string(), self.register);
                 buf.remove(r);
             }
             Command::Copy(m) => {
                 let r = m.range(buf, *cursor_index, 1, &mut None);
-                self.presenter.copy_str(buf.slice(r).to_string());
+                self.presenter
+                    .copy_str(buf.slice(r).to_string(), self.register);
             }
-            Command::Put { consume: _ } => {
-                if let Some(s) = self.presenter.pop_snip_str() {
+            Command::Repeat(_) => {}
+            Command::Put { consume } => {
+                if let Some(s) = self.presenter.snip_str(self.register, consume) {
                     buf.insert(*cursor_index, &s);
                 }
             }
@@ -152,16 +676,198 @@ impl ViewState {
         None
     }
 
-    pub fn toggle_folded(&mut self) {
-        if !self
+    /// The register a macro is being recorded into.
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(reg, _)| *reg)
+    }
+
+    pub fn begin_recording(&mut self, reg: char) {
+        self.recording = Some((reg, Vec::new()));
+    }
+
+    /// Store the keys recorded since `begin_recording`, leaving out the `q` that stopped it.
+    pub fn stop_recording(&mut self) {
+        if let Some((reg, mut keys)) = self.recording.take() {
+            keys.pop();
+            self.presenter.set_macro(reg, format_keys(&keys));
+        }
+    }
+
+    /// Remember a key typed while recording a macro.
+    pub fn record_key(&mut self, key: &KeyPress) {
+        if let Some((_, keys)) = self.recording.as_mut() {
+            keys.push(key.clone());
+        }
+    }
+
+    /// Play the macro in `reg`, or the last one played, `count` times. The keys are played before
+    /// any left over from a macro that is already playing, so that macros can call each other.
+    pub fn play_macro(&mut self, reg: Option<char>, count: usize) {
+        let reg = match reg.or(self.last_macro) {
+            Some(r) => r,
+            None => {
+                self.prev_error = Some(anyhow::anyhow!("no macro played yet"));
+                return;
+            }
+        };
+        let keys = match self
             .presenter
-            .model()
-            .node(self.cur_node)
-            .children
-            .is_empty()
-            && !self.folded_nodes.remove(&self.cur_node)
+            .config()
+            .macros
+            .get(&reg)
+            .map(|k| parse_keys(k))
         {
-            self.folded_nodes.insert(self.cur_node);
+            Some(Ok(keys)) => keys,
+            Some(Err(e)) => {
+                self.prev_error = Some(e.context(format!("macro {reg}")));
+                return;
+            }
+            None => {
+                self.prev_error = Some(anyhow::anyhow!("macro {reg} isn't recorded"));
+                return;
+            }
+        };
+        self.last_macro = Some(reg);
+        for _ in 0..count {
+            for key in keys.iter().rev() {
+                self.playback.push_front(key.clone());
+            }
+        }
+    }
+
+    /// The next key a macro has to play.
+    pub fn next_played_key(&mut self) -> Option<KeyPress> {
+        self.playback.pop_front()
+    }
+
+    /// Stop playing macros, when one goes wrong.
+    pub fn stop_playback(&mut self) {
+        self.playback.clear();
+    }
+
+    /// The nodes that operations should act on, in tree order: the visual mode selection, or
+    /// just the current node.
+    pub fn selection(&self) -> Vec<NodeId> {
+        let visual = match self.visual.as_ref() {
+            Some(v) => v,
+            None => return vec![self.cur_node],
+        };
+        let model = self.presenter.model();
+        let mut nodes = visual.marked.clone();
+        let parent = model.node(self.cur_node).parent;
+        if model.node(visual.anchor).parent == parent && parent != ROOT_PARENT_ID {
+            let siblings = &model.node(parent).children;
+            let ix = |n| siblings.iter().position(|c| *c == n).unwrap();
+            let (a, b) = (ix(visual.anchor), ix(self.cur_node));
+            nodes.extend_from_slice(&siblings[a.min(b)..=a.max(b)]);
+        } else {
+            nodes.push(self.cur_node);
+        }
+        model.top_level_in_order(&nodes)
+    }
+
+    pub fn begin_visual(&mut self) {
+        self.visual = Some(Visual {
+            anchor: self.cur_node,
+            marked: Vec::new(),
+        });
+    }
+
+    /// Mark the current node as part of the selection, or unmark it if it was marked.
+    pub fn toggle_marked(&mut self) {
+        if let Some(v) = self.visual.as_mut() {
+            if let Some(i) = v.marked.iter().position(|n| *n == self.cur_node) {
+                v.marked.remove(i);
+            } else {
+                v.marked.push(self.cur_node);
+            }
+        }
+    }
+
+    /// Carry out a fold command: fold everything below the current root, unfold everything, show
+    /// only a number of levels, fold the siblings of the cursor, or unfold the nodes above it.
+    /// The cursor then moves out of any node that was folded over it.
+    pub fn fold(&mut self, fold: FoldCommand) {
+        match fold {
+            FoldCommand::All => self.fold_to_depth(None),
+            FoldCommand::Nothing => self.folded_nodes.clear(),
+            FoldCommand::ToDepth(levels) => self.fold_to_depth(Some(levels)),
+            FoldCommand::Siblings => {
+                let model = self.presenter.model();
+                if let Some(parent) = self.motion_step(self.cur_node, TreeMotionType::Parent) {
+                    self.folded_nodes
+                        .extend(model.content(parent).children.iter().filter(|n| {
+                            **n != self.cur_node && !model.content(**n).children.is_empty()
+                        }));
+                }
+            }
+            FoldCommand::PathToCursor => self.unfold_path(),
+        }
+        self.move_out_of_folds();
+    }
+
+    /// Show `levels` levels of nodes below the current root, unfolding the nodes above the last
+    /// level and folding the ones on it, or fold every node below the root if `levels` is None.
+    fn fold_to_depth(&mut self, levels: Option<usize>) {
+        let model = self.presenter.model();
+        let mut stack = vec![(self.presenter.current_root(), 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let children = &model.content(node).children;
+            if children.is_empty() {
+                continue;
+            }
+            // the root stays unfolded when folding everything so that its children show
+            if levels.map_or(depth > 0, |l| depth >= l) {
+                self.folded_nodes.insert(node);
+                if levels.is_some() {
+                    // nothing below here can be seen, so leave it be
+                    continue;
+                }
+            } else {
+                self.folded_nodes.remove(&node);
+            }
+            stack.extend(children.iter().map(|c| (*c, depth + 1)));
+        }
+    }
+
+    /// Unfold the nodes above the cursor so that it can be seen.
+    pub fn unfold_path(&mut self) {
+        let model = self.presenter.model();
+        for n in model.ancestors(self.cur_node).skip(1) {
+            self.folded_nodes.remove(&n);
+        }
+    }
+
+    /// If the cursor is hidden inside a folded node, move it up to the outermost one.
+    fn move_out_of_folds(&mut self) {
+        let model = self.presenter.model();
+        let root = self.presenter.current_root();
+        let mut visible = self.cur_node;
+        for n in model.ancestors(self.cur_node).skip(1) {
+            if self.folded_nodes.contains(&n) {
+                visible = n;
+            }
+            if n == root {
+                break;
+            }
+        }
+        self.cur_node = visible;
+    }
+
+    /// Fold all of `nodes` that have children, or unfold them all if they are already folded.
+    pub fn toggle_folded_nodes(&mut self, nodes: &[NodeId]) {
+        let model = self.presenter.model();
+        let foldable: Vec<NodeId> = nodes
+            .iter()
+            .copied()
+            .filter(|n| !model.content(*n).children.is_empty())
+            .collect();
+        if foldable.iter().all(|n| self.folded_nodes.contains(n)) {
+            for n in foldable {
+                self.folded_nodes.remove(&n);
+            }
+        } else {
+            self.folded_nodes.extend(foldable);
         }
     }
 }
@@ -0,0 +1,117 @@
+use super::{
+    insert_mode::InsertMode,
+    picker::{Picker, PickerMode},
+    tree_mode::TreeMode,
+    tree_motion::{TreeMotion, TreeMotionType, TreeOperator},
+    KeyPress, Mode, ViewState,
+};
+use crate::model::SortKey;
+
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+/// Selecting several nodes at once, either a run of siblings from where visual mode started to
+/// the cursor or any nodes marked with space, so that an operation can act on all of them.
+#[derive(Default)]
+pub struct VisualMode;
+
+impl Mode for VisualMode {
+    fn process_key(
+        &mut self,
+        input: &KeyPress,
+        view_state: &mut ViewState,
+    ) -> Option<Box<dyn Mode>> {
+        let mods = input.mods;
+        let key = match input.physical_key {
+            PhysicalKey::Code(c) => c,
+            _ => return None,
+        };
+
+        // keys that move the cursor or change the selection
+        match key {
+            KeyCode::KeyJ if !mods.control_key() => {
+                view_state.move_by(TreeMotion {
+                    count: 1,
+                    mo: TreeMotionType::NextSibling,
+                });
+                return None;
+            }
+            KeyCode::KeyK if !mods.control_key() => {
+                view_state.move_by(TreeMotion {
+                    count: 1,
+                    mo: TreeMotionType::PrevSibling,
+                });
+                return None;
+            }
+            KeyCode::KeyL if !mods.control_key() => {
+                view_state.move_by(TreeMotion {
+                    count: 1,
+                    mo: TreeMotionType::FirstChild,
+                });
+                return None;
+            }
+            KeyCode::KeyH if !mods.control_key() => {
+                view_state.move_by(TreeMotion {
+                    count: 1,
+                    mo: TreeMotionType::Parent,
+                });
+                return None;
+            }
+            KeyCode::Space => {
+                view_state.toggle_marked();
+                return None;
+            }
+            KeyCode::Escape | KeyCode::KeyV => {
+                view_state.visual = None;
+                return Some(Box::<TreeMode>::default());
+            }
+            _ => {}
+        }
+
+        // operations on the whole selection, which end visual mode
+        let nodes = view_state.selection();
+        match key {
+            KeyCode::KeyX => view_state.apply_operator(TreeOperator::Cut, &nodes),
+            KeyCode::KeyY => view_state.apply_operator(TreeOperator::Copy, &nodes),
+            KeyCode::Minus => view_state.apply_operator(TreeOperator::Strikeout, &nodes),
+            KeyCode::KeyF => view_state.apply_operator(TreeOperator::Fold, &nodes),
+            KeyCode::KeyL => view_state.apply_operator(TreeOperator::Indent, &nodes),
+            KeyCode::KeyH => view_state.apply_operator(TreeOperator::Outdent, &nodes),
+            KeyCode::KeyW => {
+                view_state.visual = None;
+                match view_state.presenter.wrap_nodes(&nodes) {
+                    Ok(wrapper) => {
+                        view_state.cur_node = wrapper;
+                        view_state.begin_editing(false);
+                        return Some(Box::new(InsertMode));
+                    }
+                    Err(e) => view_state.prev_error = Some(e),
+                }
+            }
+            KeyCode::KeyS => {
+                view_state
+                    .presenter
+                    .sort_nodes(&nodes, &SortKey::Text, mods.shift_key());
+            }
+            KeyCode::KeyM => {
+                view_state.visual = None;
+                match view_state.presenter.begin_refile(nodes, None) {
+                    Ok(()) => {
+                        if let Some(req) = view_state.presenter.take_pick_request() {
+                            view_state.picker = Some(Picker::new(req));
+                            return Some(Box::new(PickerMode));
+                        }
+                    }
+                    Err(e) => view_state.prev_error = Some(e),
+                }
+                return Some(Box::<TreeMode>::default());
+            }
+            _ => return None,
+        }
+        view_state.visual = None;
+        Some(Box::<TreeMode>::default())
+    }
+
+    fn name(&self) -> &'static str {
+        "VISUAL"
+    }
+}